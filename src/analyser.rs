@@ -0,0 +1,209 @@
+// Static analysis of a program, the report of `info --json`
+//
+// Only the code reachable from #200 is looked at (see `platform::reachable`),
+// every instruction decoded with `Opcodes`. I is only known where an Annn set
+// it on the way there, so the stores into code and the sprites found are the
+// likely ones rather than all of them. The bytes no instruction reaches are
+// data, or code only reached through Bnnn jumps.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::chip8::LOADING_POINT;
+use crate::disasm::disassemble;
+use crate::opcodes::Opcodes;
+use crate::platform::{self, Platform};
+
+// Dxy0 draws a 16x16 sprite on SUPER-CHIP
+const LARGE_SPRITE_BYTES: usize = 32;
+
+#[derive(Serialize)]
+pub struct Report {
+    pub platform: Platform,
+    // the first instruction of the platform, none for CHIP-8
+    pub evidence: Option<Found>,
+    // bytes of the reachable instructions
+    pub code_bytes: usize,
+    // how many reachable instructions of every CHIP-8 opcode there are
+    pub instructions: BTreeMap<String, usize>,
+    // SUPER-CHIP and XO-CHIP instructions
+    pub extended: Vec<Extended>,
+    // 0nnn, machine code routines of the COSMAC VIP
+    pub sys_calls: Vec<Found>,
+    // words no platform decodes
+    pub invalid: Vec<Found>,
+    // Fx33 and Fx55 writing over reachable instructions
+    pub self_modifying: Vec<Store>,
+    pub sprites: Vec<Sprite>,
+    pub unreachable: Vec<Span>,
+}
+
+#[derive(Serialize)]
+pub struct Found {
+    pub address: u16,
+    pub opcode: String,
+}
+
+#[derive(Serialize)]
+pub struct Extended {
+    pub address: u16,
+    pub opcode: String,
+    pub platform: Platform,
+}
+
+#[derive(Serialize)]
+pub struct Store {
+    pub address: u16,
+    pub instruction: String,
+    // the bytes written, both included
+    pub start: u16,
+    pub end: u16,
+}
+
+#[derive(Serialize)]
+pub struct Sprite {
+    pub address: u16,
+    pub bytes: usize,
+    // the Dxyn drawing it
+    pub drawn_at: Vec<u16>,
+}
+
+#[derive(Serialize)]
+pub struct Span {
+    // both included
+    pub start: u16,
+    pub end: u16,
+    pub bytes: usize,
+    // how many of them are sprites
+    pub sprite_bytes: usize,
+}
+
+impl Found {
+    fn new(addr: usize, instruction_bytes: u16) -> Self {
+        Found { address: addr as u16, opcode: format!("{:04X}", instruction_bytes) }
+    }
+}
+
+pub fn analyse(program: &[u8]) -> Report {
+    let detection = platform::detect(program);
+    let reachable = platform::reachable(program);
+
+    let mut report = Report {
+        platform: detection.platform,
+        evidence: detection.evidence.map(|(addr, instruction_bytes)| Found::new(addr, instruction_bytes)),
+        code_bytes: 0,
+        instructions: BTreeMap::new(),
+        extended: Vec::new(),
+        sys_calls: Vec::new(),
+        invalid: Vec::new(),
+        self_modifying: Vec::new(),
+        sprites: Vec::new(),
+        unreachable: Vec::new(),
+    };
+
+    let mut code = BTreeSet::new();
+    let mut stores = Vec::new();
+    let mut sprites: BTreeMap<u16, Sprite> = BTreeMap::new();
+
+    for (&addr, &i) in &reachable {
+        let Some(instruction_bytes) = platform::fetch(program, addr) else {
+            continue;
+        };
+        // F000 nnnn is 4 bytes long
+        let length = if instruction_bytes == 0xF000 { 4 } else { 2 };
+        code.extend(addr..addr + length);
+
+        let x = (instruction_bytes >> 8) & 0x000F;
+        let n = (instruction_bytes & 0x000F) as usize;
+        let platform = platform::platform_of(instruction_bytes);
+        match Opcodes::try_from(instruction_bytes) {
+            _ if platform != Platform::Chip8 => {
+                report.extended.push(Extended {
+                    address: addr as u16,
+                    opcode: format!("{:04X}", instruction_bytes),
+                    platform,
+                });
+                // Dxy0 draws a 16x16 sprite
+                if let (0xD000, Some(i)) = (instruction_bytes & 0xF00F, i) {
+                    drawn(&mut sprites, i, LARGE_SPRITE_BYTES, addr);
+                }
+            }
+            Ok(Opcodes::SysExecute) => {
+                report.sys_calls.push(Found::new(addr, instruction_bytes));
+                count(&mut report.instructions, Opcodes::SysExecute);
+            }
+            Ok(instruction) => {
+                count(&mut report.instructions, instruction);
+
+                let Some(i) = i else {
+                    continue;
+                };
+                match instruction {
+                    Opcodes::StoreBCD => stores.push((addr, instruction_bytes, i, i.saturating_add(2))),
+                    Opcodes::StoreRegsInMemoryFromRegI => stores.push((addr, instruction_bytes, i, i.saturating_add(x))),
+                    Opcodes::DrawSprite => drawn(&mut sprites, i, n, addr),
+                    _ => {}
+                }
+            }
+            // the decoder only takes 0000 for a 0nnn
+            Err(_) if instruction_bytes & 0xF000 == 0 => {
+                report.sys_calls.push(Found::new(addr, instruction_bytes));
+                count(&mut report.instructions, Opcodes::SysExecute);
+            }
+            Err(_) => report.invalid.push(Found::new(addr, instruction_bytes)),
+        }
+    }
+
+    report.code_bytes = code.len();
+    report.self_modifying = stores
+        .into_iter()
+        .filter(|(_, _, start, end)| (*start..=*end).any(|addr| code.contains(&(addr as usize))))
+        .map(|(addr, instruction_bytes, start, end)| Store {
+            address: addr as u16,
+            instruction: disassemble(instruction_bytes),
+            start,
+            end,
+        })
+        .collect();
+
+    let sprite_bytes: BTreeSet<usize> = sprites
+        .values()
+        .flat_map(|sprite| sprite.address as usize..sprite.address as usize + sprite.bytes)
+        .collect();
+    report.sprites = sprites.into_values().collect();
+    report.unreachable = unreachable(program, &code, &sprite_bytes);
+
+    report
+}
+
+// The sprite of `bytes` bytes at `i` is drawn by the Dxyn at `addr`
+fn drawn(sprites: &mut BTreeMap<u16, Sprite>, i: u16, bytes: usize, addr: usize) {
+    let sprite = sprites.entry(i).or_insert(Sprite { address: i, bytes: 0, drawn_at: Vec::new() });
+    sprite.bytes = sprite.bytes.max(bytes);
+    sprite.drawn_at.push(addr as u16);
+}
+
+fn count(instructions: &mut BTreeMap<String, usize>, instruction: Opcodes) {
+    *instructions.entry(instruction.to_string()).or_default() += 1;
+}
+
+// The runs of program bytes that are not part of any reachable instruction
+fn unreachable(program: &[u8], code: &BTreeSet<usize>, sprite_bytes: &BTreeSet<usize>) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let end = LOADING_POINT + program.len();
+
+    for addr in (LOADING_POINT..end).filter(|addr| !code.contains(addr)) {
+        let sprite = sprite_bytes.contains(&addr) as usize;
+        match spans.last_mut() {
+            Some(span) if span.end as usize + 1 == addr => {
+                span.end = addr as u16;
+                span.bytes += 1;
+                span.sprite_bytes += sprite;
+            }
+            _ => spans.push(Span { start: addr as u16, end: addr as u16, bytes: 1, sprite_bytes: sprite }),
+        }
+    }
+
+    spans
+}
@@ -0,0 +1,290 @@
+// Assembler for the mnemonics the disassembler prints
+//
+// One instruction per line, in Cowgod's notation:
+//     loop:  LD   V0, #0A       ; comment
+//            JP   loop
+// A label ends with a colon and stands for the address of what follows it.
+// Numbers are decimal, hex with # or 0x, or binary with % or 0b. DB and DW
+// put bytes and 16 bit words, such as sprites, in the program:
+//     ship:  DB   %00111100, %11111111, #3C
+// The program is assembled to be loaded at #200.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::chip8::{LOADING_POINT, MEMORY_SIZE};
+
+const MNEMONICS: [&str; 22] = [
+    "CLS", "RET", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "DB", "DW",
+];
+
+// Operands that are not registers nor numbers, they cannot be labels
+const KEYWORDS: [&str; 6] = ["I", "DT", "ST", "K", "F", "B"];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AsmError {
+    line: usize,
+    message: String,
+}
+impl std::error::Error for AsmError { }
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Assembly error on line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(u16),
+    // a number or the address of a label
+    Value(u16),
+    I,
+    // [I], the memory I points to
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    Bcd,
+}
+
+// A line of source that assembles to something
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    // first pass, for the addresses of the labels
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = LOADING_POINT;
+
+    for (idx, text) in source.lines().enumerate() {
+        let line = idx + 1;
+        let error = |message: String| AsmError { line, message };
+
+        let mut text = text.split(';').next().unwrap_or_default().trim();
+        while let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if !is_label(label) {
+                return Err(error(format!("'{}' is not a valid label", label)));
+            }
+            if labels.insert(label.to_string(), address as u16).is_some() {
+                return Err(error(format!("label '{}' is defined twice", label)));
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let operands: Vec<&str> = match operands.trim() {
+            "" => Vec::new(),
+            operands => operands.split(',').map(str::trim).collect(),
+        };
+
+        address += match mnemonic.as_str() {
+            "DB" => operands.len(),
+            "DW" => 2 * operands.len(),
+            _ => 2,
+        };
+        if address > MEMORY_SIZE {
+            return Err(error("the program does not fit in memory".to_string()));
+        }
+        statements.push(Statement { line, mnemonic, operands });
+    }
+
+    // second pass, with every label known
+    let mut program = Vec::new();
+    for statement in statements {
+        let error = |message: String| AsmError { line: statement.line, message };
+        let operands = statement
+            .operands
+            .iter()
+            .map(|text| operand(text, &labels))
+            .collect::<Result<Vec<Operand>, String>>()
+            .map_err(error)?;
+        program.extend(encode(&statement.mnemonic, &operands).map_err(error)?);
+    }
+
+    Ok(program)
+}
+
+fn encode(mnemonic: &str, operands: &[Operand]) -> Result<Vec<u8>, String> {
+    use Operand::*;
+
+    match mnemonic {
+        "DB" => {
+            return operands
+                .iter()
+                .map(|operand| match operand {
+                    Value(value) => bits(*value, 8).map(|value| value as u8),
+                    _ => Err("DB takes numbers".to_string()),
+                })
+                .collect();
+        }
+        "DW" => {
+            let words = operands
+                .iter()
+                .map(|operand| match operand {
+                    Value(value) => Ok(value.to_be_bytes()),
+                    _ => Err("DW takes numbers".to_string()),
+                })
+                .collect::<Result<Vec<[u8; 2]>, String>>()?;
+            return Ok(words.concat());
+        }
+        _ => {}
+    }
+
+    let word = match (mnemonic, operands) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SYS", &[Value(nnn)]) => bits(nnn, 12)?,
+        ("JP", &[Value(nnn)]) => 0x1000 | bits(nnn, 12)?,
+        // JP Vx, nnn for the interpreters adding VX, x being the top digit of nnn
+        ("JP", &[Register(x), Value(nnn)]) if x == 0 || x == nnn >> 8 => 0xB000 | bits(nnn, 12)?,
+        ("CALL", &[Value(nnn)]) => 0x2000 | bits(nnn, 12)?,
+        ("SE", &[Register(x), Value(kk)]) => 0x3000 | x << 8 | bits(kk, 8)?,
+        ("SE", &[Register(x), Register(y)]) => 0x5000 | x << 8 | y << 4,
+        ("SNE", &[Register(x), Value(kk)]) => 0x4000 | x << 8 | bits(kk, 8)?,
+        ("SNE", &[Register(x), Register(y)]) => 0x9000 | x << 8 | y << 4,
+        ("LD", &[Register(x), Value(kk)]) => 0x6000 | x << 8 | bits(kk, 8)?,
+        ("LD", &[Register(x), Register(y)]) => 0x8000 | x << 8 | y << 4,
+        ("LD", &[I, Value(nnn)]) => 0xA000 | bits(nnn, 12)?,
+        ("LD", &[Register(x), DelayTimer]) => 0xF007 | x << 8,
+        ("LD", &[Register(x), Key]) => 0xF00A | x << 8,
+        ("LD", &[DelayTimer, Register(x)]) => 0xF015 | x << 8,
+        ("LD", &[SoundTimer, Register(x)]) => 0xF018 | x << 8,
+        ("LD", &[Font, Register(x)]) => 0xF029 | x << 8,
+        ("LD", &[Bcd, Register(x)]) => 0xF033 | x << 8,
+        ("LD", &[IndirectI, Register(x)]) => 0xF055 | x << 8,
+        ("LD", &[Register(x), IndirectI]) => 0xF065 | x << 8,
+        ("ADD", &[Register(x), Value(kk)]) => 0x7000 | x << 8 | bits(kk, 8)?,
+        ("ADD", &[Register(x), Register(y)]) => 0x8004 | x << 8 | y << 4,
+        ("ADD", &[I, Register(x)]) => 0xF01E | x << 8,
+        ("OR", &[Register(x), Register(y)]) => 0x8001 | x << 8 | y << 4,
+        ("AND", &[Register(x), Register(y)]) => 0x8002 | x << 8 | y << 4,
+        ("XOR", &[Register(x), Register(y)]) => 0x8003 | x << 8 | y << 4,
+        ("SUB", &[Register(x), Register(y)]) => 0x8005 | x << 8 | y << 4,
+        ("SHR", &[Register(x)]) => 0x8006 | x << 8 | x << 4,
+        ("SHR", &[Register(x), Register(y)]) => 0x8006 | x << 8 | y << 4,
+        ("SUBN", &[Register(x), Register(y)]) => 0x8007 | x << 8 | y << 4,
+        ("SHL", &[Register(x)]) => 0x800E | x << 8 | x << 4,
+        ("SHL", &[Register(x), Register(y)]) => 0x800E | x << 8 | y << 4,
+        ("RND", &[Register(x), Value(kk)]) => 0xC000 | x << 8 | bits(kk, 8)?,
+        ("DRW", &[Register(x), Register(y), Value(n)]) => 0xD000 | x << 8 | y << 4 | bits(n, 4)?,
+        ("SKP", &[Register(x)]) => 0xE09E | x << 8,
+        ("SKNP", &[Register(x)]) => 0xE0A1 | x << 8,
+        _ if MNEMONICS.contains(&mnemonic) => return Err(format!("invalid operands for {}", mnemonic)),
+        _ => return Err(format!("unknown instruction '{}'", mnemonic)),
+    };
+
+    Ok(u16::to_be_bytes(word).to_vec())
+}
+
+fn operand(text: &str, labels: &HashMap<String, u16>) -> Result<Operand, String> {
+    if text.starts_with(|c: char| c.is_ascii_digit() || c == '#' || c == '%') {
+        return number(text).map(Operand::Value);
+    }
+
+    let upper = text.to_ascii_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "B" => Operand::Bcd,
+        _ => match upper.strip_prefix('V').and_then(|x| u16::from_str_radix(x, 16).ok()) {
+            Some(x) if upper.len() == 2 => Operand::Register(x),
+            _ => match labels.get(text) {
+                Some(address) => Operand::Value(*address),
+                None => return Err(format!("unknown label '{}'", text)),
+            },
+        },
+    };
+    Ok(operand)
+}
+
+fn number(text: &str) -> Result<u16, String> {
+    let (digits, radix) = if let Some(digits) = text.strip_prefix('#') {
+        (digits, 16)
+    }
+    else if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (digits, 16)
+    }
+    else if let Some(digits) = text.strip_prefix('%').or_else(|| text.strip_prefix("0b")) {
+        (digits, 2)
+    }
+    else {
+        (text, 10)
+    };
+
+    u16::from_str_radix(digits, radix).map_err(|_| format!("'{}' is not a number", text))
+}
+
+// `value` if it fits in `count` bits
+fn bits(value: u16, count: u32) -> Result<u16, String> {
+    if value >> count != 0 {
+        return Err(format!("#{:X} does not fit in {} bits", value, count));
+    }
+    Ok(value)
+}
+
+fn is_label(text: &str) -> bool {
+    let upper = text.to_ascii_uppercase();
+    let register = upper.len() == 2 && upper.starts_with('V') && upper[1..].chars().all(|c| c.is_ascii_hexdigit());
+
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !MNEMONICS.contains(&upper.as_str())
+        && !KEYWORDS.contains(&upper.as_str())
+        && !register
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::disasm::listing;
+    use crate::quirks::QuirkPreset;
+
+    fn error(source: &str) -> String {
+        assemble(source).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn listings_assemble_back_to_the_programs() {
+        for entry in fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/programs")).unwrap() {
+            let path = entry.unwrap().path();
+            let program = fs::read(&path).unwrap();
+            for preset in [QuirkPreset::Classic, QuirkPreset::Schip] {
+                assert_eq!(assemble(&listing(&program, &preset.quirks())).unwrap(), program, "{} {}", path.display(), preset);
+            }
+        }
+    }
+
+    #[test]
+    fn label_defined_twice() {
+        assert_eq!(error("loop: CLS\nloop: JP loop"), "Assembly error on line 2: label 'loop' is defined twice");
+    }
+
+    #[test]
+    fn value_out_of_range() {
+        assert_eq!(error("LD V0, #100"), "Assembly error on line 1: #100 does not fit in 8 bits");
+        assert_eq!(error("JP #1000"), "Assembly error on line 1: #1000 does not fit in 12 bits");
+    }
+
+    #[test]
+    fn unknown_mnemonic() {
+        assert_eq!(error("CLS\nMOV V0, V1"), "Assembly error on line 2: unknown instruction 'MOV'");
+    }
+}
@@ -0,0 +1,316 @@
+// The CHIP-8 buzzer
+//
+// The sound timer only says whether the buzzer sounds, so the tone itself is
+// up to the emulator: its pitch, waveform and volume are configurable. The
+// tone fades in and out over a few milliseconds, which avoids clicks.
+//
+// The core reports when the buzzer starts and stops in emulated time. The
+// frontend renders those events into samples after every frame and queues
+// them in a ring buffer the audio device plays from, so beeps keep their
+// emulated length whatever the host timing is.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use sdl2::audio::{AudioCallback, AudioSpec};
+
+use crate::chip8::BuzzerEvent;
+
+// Duration of the fade in and out of the tone, in seconds
+const RAMP_TIME: f32 = 0.005;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sine,
+    Noise,
+}
+
+const WAVEFORMS: [(&str, Waveform); 4] = [
+    ("square", Waveform::Square),
+    ("triangle", Waveform::Triangle),
+    ("sine", Waveform::Sine),
+    ("noise", Waveform::Noise),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct WaveformError(String);
+impl std::error::Error for WaveformError { }
+
+impl fmt::Display for WaveformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid waveform: '{}', expected square, triangle, sine or noise", self.0)
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = WaveformError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        WAVEFORMS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, waveform)| *waveform)
+            .ok_or_else(|| WaveformError(s.to_string()))
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = WAVEFORMS.iter().find(|(_, waveform)| waveform == self).unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+impl Waveform {
+    // The waveform following this one, for cycling at runtime
+    pub fn next(&self) -> Waveform {
+        let idx = WAVEFORMS.iter().position(|(_, waveform)| waveform == self).unwrap();
+        WAVEFORMS[(idx + 1) % WAVEFORMS.len()].1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuzzerSettings {
+    // pitch in Hz
+    pub frequency: f32,
+    pub waveform: Waveform,
+    // from 0.0 to 1.0
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for BuzzerSettings {
+    // the tone the emulator always had
+    fn default() -> Self {
+        BuzzerSettings {
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+            muted: false,
+        }
+    }
+}
+
+pub struct Buzzer {
+    pub settings: BuzzerSettings,
+    sample_rate: f32,
+
+    // position in the current period, from 0.0 to 1.0
+    phase: f32,
+    // whether the tone should sound
+    gate: bool,
+    // current amplitude, ramping towards the volume or silence
+    level: f32,
+
+    // noise generator state and its current output
+    noise_state: u32,
+    noise_value: f32,
+}
+
+impl Buzzer {
+    pub fn new(settings: BuzzerSettings, sample_rate: i32) -> Self {
+        Buzzer {
+            settings,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            gate: false,
+            level: 0.0,
+            noise_state: 0x1234_5678,
+            noise_value: 0.0,
+        }
+    }
+
+    // Starts or stops the tone, with the sound timer
+    pub fn set_tone(&mut self, on: bool) {
+        self.gate = on;
+    }
+
+    fn sample(&mut self) -> f32 {
+        match self.settings.waveform {
+            Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+            Waveform::Noise => self.noise_value,
+        }
+    }
+
+    // xorshift, a new noise value every half period so the pitch still
+    // colours the noise
+    fn next_noise(&mut self) {
+        self.noise_state ^= self.noise_state << 13;
+        self.noise_state ^= self.noise_state >> 17;
+        self.noise_state ^= self.noise_state << 5;
+        self.noise_value = self.noise_state as f32 / u32::MAX as f32 * 2.0 - 1.0;
+    }
+
+    // Fills `out` with the next samples of the tone
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let target = if self.gate && !self.settings.muted { self.settings.volume } else { 0.0 };
+        let ramp_step = 1.0 / (RAMP_TIME * self.sample_rate);
+        let phase_inc = self.settings.frequency / self.sample_rate;
+
+        for x in out.iter_mut() {
+            self.level = if self.level < target {
+                (self.level + ramp_step).min(target)
+            } else {
+                (self.level - ramp_step).max(target)
+            };
+
+            *x = if self.level > 0.0 { self.sample() * self.level } else { 0.0 };
+
+            let half = (self.phase * 2.0) as u32;
+            self.phase = (self.phase + phase_inc) % 1.0;
+            if (self.phase * 2.0) as u32 != half {
+                self.next_noise();
+            }
+        }
+    }
+}
+
+// Renders the buzzer events of the core into samples, following emulated
+// time
+pub struct SoundRenderer {
+    pub buzzer: Buzzer,
+    sample_rate: f64,
+
+    // emulated time rendered so far, in microseconds, and the fraction of a
+    // sample it ended in
+    rendered: u64,
+    remainder: f64,
+}
+
+impl SoundRenderer {
+    pub fn new(settings: BuzzerSettings, sample_rate: i32) -> Self {
+        SoundRenderer {
+            buzzer: Buzzer::new(settings, sample_rate),
+            sample_rate: sample_rate as f64,
+            rendered: 0,
+            remainder: 0.0,
+        }
+    }
+
+    // Appends to `out` the sound from where the last call stopped up to the
+    // emulated time `until`, with the buzzer changing at `events`. With a
+    // `speed` above 1 the emulated time is played faster, so less samples
+    // are rendered for it.
+    pub fn render(&mut self, events: &[BuzzerEvent], until: u64, speed: f64, out: &mut Vec<f32>) {
+        for event in events {
+            self.render_until(event.time.min(until), speed, out);
+            self.buzzer.set_tone(event.on);
+        }
+        self.render_until(until, speed, out);
+    }
+
+    // Carries on from the emulated time `time` with the buzzer `on`, after
+    // the machine was reset or restored
+    pub fn resync(&mut self, time: u64, on: bool) {
+        self.rendered = time;
+        self.remainder = 0.0;
+        self.buzzer.set_tone(on);
+    }
+
+    fn render_until(&mut self, time: u64, speed: f64, out: &mut Vec<f32>) {
+        if time <= self.rendered {
+            return;
+        }
+
+        let exact = (time - self.rendered) as f64 * self.sample_rate / 1_000_000.0 / speed + self.remainder;
+        let count = exact.floor();
+        self.remainder = exact - count;
+        self.rendered = time;
+
+        let start = out.len();
+        out.resize(start + count as usize, 0.0);
+        self.buzzer.fill(&mut out[start..]);
+    }
+}
+
+// Samples on their way from the emulation to the audio device
+#[derive(Clone, Default)]
+pub struct SampleRing {
+    samples: Arc<Mutex<VecDeque<f32>>>,
+}
+
+impl SampleRing {
+    // Queues `samples`, dropping the oldest ones beyond `limit` so that the
+    // sound never lags behind the picture
+    pub fn push(&self, samples: &[f32], limit: usize) {
+        let mut queue = self.samples.lock().unwrap();
+        queue.extend(samples);
+        let excess = queue.len().saturating_sub(limit);
+        queue.drain(..excess);
+    }
+}
+
+// How many samples may wait in the ring for a device playing `spec`: two of
+// its buffers and four frames, which last longer when `speed` slows the
+// emulation down
+pub fn queue_limit(spec: &AudioSpec, speed: f64) -> usize {
+    2 * spec.samples as usize + (4.0 * spec.freq as f64 / 60.0 / speed).ceil() as usize
+}
+
+// The audio device callback, playing the samples of the ring
+pub struct RingPlayer {
+    ring: SampleRing,
+
+    // samples to gather before playing, at the start and after running dry
+    prefill: usize,
+    buffering: bool,
+}
+
+impl RingPlayer {
+    pub fn new(ring: SampleRing, spec: &AudioSpec) -> Self {
+        RingPlayer {
+            ring,
+            prefill: spec.samples as usize + spec.freq as usize / 60,
+            buffering: true,
+        }
+    }
+}
+
+impl AudioCallback for RingPlayer {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let mut samples = self.ring.samples.lock().unwrap();
+
+        if self.buffering && samples.len() < self.prefill {
+            out.fill(0.0);
+            return;
+        }
+        self.buffering = false;
+
+        for x in out.iter_mut() {
+            *x = samples.pop_front().unwrap_or_else(|| {
+                self.buffering = true;
+                0.0
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::audio::AudioFormat;
+
+    use super::*;
+    use crate::chip8::FRAME_TIME;
+
+    #[test]
+    fn slow_motion_frame_fits_in_the_queue() {
+        let spec = AudioSpec { freq: 48000, format: AudioFormat::F32LSB, channels: 1, silence: 0, samples: 1024, size: 4096 };
+        let mut sound = SoundRenderer::new(BuzzerSettings::default(), spec.freq);
+        let mut samples = Vec::new();
+        sound.render(&[], FRAME_TIME as u64, 0.1, &mut samples);
+
+        let ring = SampleRing::default();
+        ring.push(&samples, queue_limit(&spec, 0.1));
+        assert_eq!(samples.len(), 7999);
+        assert_eq!(ring.samples.lock().unwrap().len(), samples.len());
+    }
+}
@@ -0,0 +1,302 @@
+// Control-flow graph of a program, for `cfg`
+//
+// The code reachable from #200 is cut into basic blocks, linked by jumps
+// (1nnn), calls (2nnn), skips and the instruction that follows, and grouped
+// into subroutines: the blocks reached from #200 or from a call target
+// without going through another call, until 00EE. Bnnn jumps to nnn plus V0,
+// or VX with the `jump_vx` quirk, which only a run knows, so its targets come
+// from the JSON summary of a `--coverage` run; the ones with no known target
+// are listed as unresolved.
+//
+// As Graphviz DOT, one cluster per subroutine:
+//     chip-8 cfg programs/MERLIN | dot -Tsvg -o merlin.svg
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::chip8::LOADING_POINT;
+use crate::disasm::disassemble_with;
+use crate::platform::fetch;
+use crate::quirks::Quirks;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+    // the next instruction, or a skip not taken
+    Next,
+    Jump,
+    Call,
+    // from a call to the instruction after it, where the subroutine returns
+    Return,
+    // a skip taken
+    Skip,
+    // Bnnn
+    Indirect,
+}
+
+#[derive(Serialize)]
+pub struct Graph {
+    pub blocks: Vec<Block>,
+    pub edges: Vec<Edge>,
+    pub subroutines: Vec<Subroutine>,
+    // Bnnn jumps no target is known for
+    pub unresolved: Vec<u16>,
+}
+
+#[derive(Serialize)]
+pub struct Block {
+    pub start: u16,
+    // the address of the last instruction
+    pub end: u16,
+    // the entry of the subroutine it is drawn in
+    pub subroutine: u16,
+    pub instructions: Vec<Instruction>,
+}
+
+#[derive(Serialize)]
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: String,
+    pub text: String,
+}
+
+// From the block ending in the instruction to the block starting at `to`
+#[derive(Serialize)]
+pub struct Edge {
+    pub from: u16,
+    pub to: u16,
+    pub kind: EdgeKind,
+    // the register an indirect jump adds to nnn
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub register: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct Subroutine {
+    pub entry: u16,
+    // the starts of its blocks, a block shared by subroutines is in all
+    pub blocks: Vec<u16>,
+    // the addresses of the calls to it
+    pub callers: Vec<u16>,
+    // whether a 00EE ends it, the main program and jump tables don't
+    pub returns: bool,
+}
+
+// `indirect` gives the addresses a Bnnn at an address was seen jumping to,
+// `quirks` the register it adds
+pub fn build(program: &[u8], indirect: &BTreeMap<usize, BTreeSet<usize>>, quirks: &Quirks) -> Graph {
+    // an empty or 1 byte program has no instruction to start from
+    if fetch(program, LOADING_POINT).is_none() {
+        return Graph { blocks: Vec::new(), edges: Vec::new(), subroutines: Vec::new(), unresolved: Vec::new() };
+    }
+
+    // the reachable instructions and where they lead
+    let mut successors: BTreeMap<usize, Vec<(usize, EdgeKind)>> = BTreeMap::new();
+    let mut pending = vec![LOADING_POINT];
+    while let Some(addr) = pending.pop() {
+        if successors.contains_key(&addr) {
+            continue;
+        }
+        let Some(instruction_bytes) = fetch(program, addr) else {
+            continue;
+        };
+        let next = successors_of(program, addr, instruction_bytes, indirect);
+        pending.extend(next.iter().map(|(target, _)| *target));
+        successors.insert(addr, next);
+    }
+    // but for targets out of the program
+    for next in successors.values_mut() {
+        next.retain(|(target, _)| fetch(program, *target).is_some());
+    }
+
+    let ends_block = |addr: &usize| match successors[addr].as_slice() {
+        [(next, EdgeKind::Next)] => *next != addr + length(program, *addr),
+        _ => true,
+    };
+    let mut leaders = BTreeSet::from([LOADING_POINT]);
+    for (addr, next) in &successors {
+        let block_end = ends_block(addr);
+        leaders.extend(
+            next.iter()
+                .filter(|(_, kind)| block_end || *kind != EdgeKind::Next)
+                .map(|(target, _)| *target),
+        );
+    }
+
+    // every block runs from a leader to a block end or the next leader
+    let mut blocks = BTreeMap::new();
+    for &start in &leaders {
+        let mut instructions = vec![start];
+        let mut addr = start;
+        while !ends_block(&addr) {
+            let next = addr + length(program, addr);
+            if leaders.contains(&next) {
+                break;
+            }
+            instructions.push(next);
+            addr = next;
+        }
+        blocks.insert(start, instructions);
+    }
+
+    let edges: Vec<Edge> = blocks
+        .iter()
+        .flat_map(|(start, instructions)| {
+            let last = instructions[instructions.len() - 1];
+            let register = fetch(program, last).map(|bytes| quirks.jump_register(bytes));
+            successors[&last].iter().map(move |(target, kind)| Edge {
+                from: *start as u16,
+                to: *target as u16,
+                kind: *kind,
+                register: register.filter(|_| *kind == EdgeKind::Indirect),
+            })
+        })
+        .collect();
+
+    // main first, then the subroutines in order
+    let mut entries = vec![LOADING_POINT as u16];
+    entries.extend(
+        edges
+            .iter()
+            .filter(|edge| edge.kind == EdgeKind::Call && edge.to != LOADING_POINT as u16)
+            .map(|edge| edge.to)
+            .collect::<BTreeSet<u16>>(),
+    );
+
+    let mut owner: BTreeMap<u16, u16> = BTreeMap::new();
+    let subroutines: Vec<Subroutine> = entries
+        .iter()
+        .map(|&entry| {
+            let members = subroutine_blocks(entry, &edges);
+            for block in &members {
+                owner.entry(*block).or_insert(entry);
+            }
+            Subroutine {
+                entry,
+                returns: members
+                    .iter()
+                    .any(|block| blocks[&(*block as usize)].last().and_then(|addr| fetch(program, *addr)) == Some(0x00EE)),
+                blocks: members.into_iter().collect(),
+                callers: edges
+                    .iter()
+                    .filter(|edge| edge.kind == EdgeKind::Call && edge.to == entry)
+                    .map(|edge| *blocks[&(edge.from as usize)].last().unwrap() as u16)
+                    .collect(),
+            }
+        })
+        .collect();
+
+    let unresolved = successors
+        .iter()
+        .filter(|(addr, next)| fetch(program, **addr).is_some_and(|bytes| bytes & 0xF000 == 0xB000) && next.is_empty())
+        .map(|(addr, _)| *addr as u16)
+        .collect();
+
+    let blocks = blocks
+        .into_iter()
+        .map(|(start, addresses)| Block {
+            start: start as u16,
+            end: addresses[addresses.len() - 1] as u16,
+            subroutine: owner[&(start as u16)],
+            instructions: addresses
+                .into_iter()
+                .map(|addr| {
+                    let instruction_bytes = fetch(program, addr).unwrap();
+                    Instruction {
+                        address: addr as u16,
+                        opcode: format!("{:04X}", instruction_bytes),
+                        text: disassemble_with(instruction_bytes, quirks),
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+
+    Graph { blocks, edges, subroutines, unresolved }
+}
+
+impl Graph {
+    pub fn dot(&self) -> String {
+        let mut text = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        for subroutine in &self.subroutines {
+            let name = match subroutine.entry as usize {
+                LOADING_POINT => "main".to_string(),
+                entry => format!("L{:03X}", entry),
+            };
+            let _ = writeln!(text, "    subgraph cluster_{:03X} {{\n        label=\"{}\";", subroutine.entry, name);
+            for block in self.blocks.iter().filter(|block| block.subroutine == subroutine.entry) {
+                let mut label = String::new();
+                for instruction in &block.instructions {
+                    label += &format!("{:03X}  {}\\l", instruction.address, instruction.text);
+                }
+                let unresolved = if self.unresolved.contains(&block.end) { ", color=red" } else { "" };
+                let _ = writeln!(text, "        L{:03X} [label=\"{}\"{}];", block.start, label, unresolved);
+            }
+            text += "    }\n";
+        }
+
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Next => String::new(),
+                EdgeKind::Jump => String::from(" [style=bold]"),
+                EdgeKind::Call => String::from(" [color=blue, label=\"call\"]"),
+                EdgeKind::Return => String::from(" [style=dashed]"),
+                EdgeKind::Skip => String::from(" [color=darkgreen, label=\"skip\"]"),
+                EdgeKind::Indirect => format!(" [style=dotted, label=\"V{:X}\"]", edge.register.unwrap_or_default()),
+            };
+            let _ = writeln!(text, "    L{:03X} -> L{:03X}{};", edge.from, edge.to, style);
+        }
+
+        text += "}\n";
+        text
+    }
+}
+
+// Where the instruction at `addr` goes next
+fn successors_of(program: &[u8], addr: usize, instruction_bytes: u16, indirect: &BTreeMap<usize, BTreeSet<usize>>) -> Vec<(usize, EdgeKind)> {
+    let nnn = (instruction_bytes & 0x0FFF) as usize;
+    let next = addr + length(program, addr);
+    // a skip goes over a whole instruction, F000 nnnn being 4 bytes
+    let skipped = next + length(program, next);
+
+    match instruction_bytes {
+        // RET and exit
+        0x00EE | 0x00FD => Vec::new(),
+        0x1000..=0x1FFF => vec![(nnn, EdgeKind::Jump)],
+        0x2000..=0x2FFF => vec![(nnn, EdgeKind::Call), (next, EdgeKind::Return)],
+        0xB000..=0xBFFF => match indirect.get(&addr) {
+            Some(targets) => targets.iter().map(|target| (*target, EdgeKind::Indirect)).collect(),
+            None => Vec::new(),
+        },
+        0x3000..=0x4FFF => vec![(next, EdgeKind::Next), (skipped, EdgeKind::Skip)],
+        _ if matches!(instruction_bytes & 0xF00F, 0x5000 | 0x9000) || matches!(instruction_bytes & 0xF0FF, 0xE09E | 0xE0A1) => {
+            vec![(next, EdgeKind::Next), (skipped, EdgeKind::Skip)]
+        }
+        _ => vec![(next, EdgeKind::Next)],
+    }
+}
+
+fn length(program: &[u8], addr: usize) -> usize {
+    if fetch(program, addr) == Some(0xF000) { 4 } else { 2 }
+}
+
+// The blocks reached from `entry` without following calls
+fn subroutine_blocks(entry: u16, edges: &[Edge]) -> BTreeSet<u16> {
+    let mut members = BTreeSet::new();
+    let mut pending = vec![entry];
+    while let Some(block) = pending.pop() {
+        if !members.insert(block) {
+            continue;
+        }
+        pending.extend(
+            edges
+                .iter()
+                .filter(|edge| edge.from == block && edge.kind != EdgeKind::Call)
+                .map(|edge| edge.to),
+        );
+    }
+    members
+}
@@ -0,0 +1,285 @@
+// Cheats: finding where a game keeps a value, and holding it there
+//
+// A search starts with every byte of `memory` and V0-VF as candidates and
+// narrows them down from one frame to another, to the values that changed,
+// stayed the same, went up or down, or equal a number. A value found can
+// then be frozen, written again before every frame, or poked once. Named
+// cheats are kept per program, by the SHA-1 of the ROM, in chip-8/cheats.toml
+// next to the configuration file:
+//
+//   [[6f6509f38220e057a7e32ebb22dd353c1078e3e7]]
+//   name = "lives"
+//   target = "2F0"      # a memory address in hex, or V0 to VF
+//   value = 3
+//   freeze = true       # false to poke it once
+//
+// The terminal debugger drives the search (see its `:search` command) and
+// `--cheat NAME` turns saved cheats on from the start.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::chip8::{Chip8, MEMORY_SIZE, REGISTERS_COUNT};
+use crate::config;
+use crate::romdb::rom_hash;
+use crate::Result;
+
+const CHEATS_FILE: &str = "cheats.toml";
+
+// The matches listed after a search, the rest are only counted
+const MATCHES_SHOWN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Target {
+    Memory(u16),
+    Register(u8),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CheatError(String);
+impl std::error::Error for CheatError { }
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Target {
+    type Err = CheatError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let register = s
+            .strip_prefix(['v', 'V'])
+            .and_then(|idx| u8::from_str_radix(idx, 16).ok())
+            .filter(|idx| (*idx as usize) < REGISTERS_COUNT);
+        let addr = u16::from_str_radix(s.trim_start_matches("0x").trim_start_matches('#'), 16)
+            .ok()
+            .filter(|addr| (*addr as usize) < MEMORY_SIZE);
+
+        match (register, addr) {
+            (Some(idx), _) if s.len() == 2 => Ok(Target::Register(idx)),
+            (_, Some(addr)) => Ok(Target::Memory(addr)),
+            _ => Err(CheatError(format!("'{}' is neither a memory address nor V0 to VF", s))),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Memory(addr) => write!(f, "{:03X}", addr),
+            Target::Register(idx) => write!(f, "V{:X}", idx),
+        }
+    }
+}
+
+impl Serialize for Target {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Target {
+    fn read(&self, emulator: &Chip8) -> u8 {
+        match *self {
+            Target::Memory(addr) => emulator.memory()[addr as usize],
+            Target::Register(idx) => emulator.registers()[idx as usize],
+        }
+    }
+
+    fn write(&self, emulator: &mut Chip8, value: u8) {
+        match *self {
+            Target::Memory(addr) => emulator.memory_mut()[addr as usize] = value,
+            Target::Register(idx) => emulator.registers_mut()[idx as usize] = value,
+        }
+    }
+}
+
+// How the values kept by a search compare to the previous ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Changed,
+    Same,
+    Up,
+    Down,
+    Equal(u8),
+}
+
+impl FromStr for Comparison {
+    type Err = CheatError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "changed" => Ok(Comparison::Changed),
+            "same" => Ok(Comparison::Same),
+            "up" => Ok(Comparison::Up),
+            "down" => Ok(Comparison::Down),
+            _ => s
+                .strip_prefix('=')
+                .and_then(|value| u8::from_str_radix(value, 16).ok())
+                .map(Comparison::Equal)
+                .ok_or_else(|| CheatError(format!("'{}' is not changed, same, up, down or =VALUE", s))),
+        }
+    }
+}
+
+impl Comparison {
+    fn keeps(&self, previous: u8, value: u8) -> bool {
+        match *self {
+            Comparison::Changed => value != previous,
+            Comparison::Same => value == previous,
+            Comparison::Up => value > previous,
+            Comparison::Down => value < previous,
+            Comparison::Equal(expected) => value == expected,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cheat {
+    pub name: String,
+    pub target: Target,
+    pub value: u8,
+    #[serde(default)]
+    pub freeze: bool,
+}
+
+#[derive(Default)]
+pub struct Cheats {
+    // the cheats file and the cheats of every program in it
+    path: Option<PathBuf>,
+    saved: BTreeMap<String, Vec<Cheat>>,
+    // SHA-1 of the running program
+    rom: String,
+
+    // the values written before every frame
+    frozen: BTreeMap<Target, u8>,
+    // the candidates of the search and their values at the last step
+    search: Option<Vec<(Target, u8)>>,
+}
+
+impl Cheats {
+    // Reads the cheats file, if there is one, for `program`
+    pub fn load(program: &[u8]) -> Result<Self> {
+        let path = config::user_directory().map(|dir| dir.join(CHEATS_FILE));
+        let saved = match &path {
+            Some(path) if path.exists() => {
+                let text = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+                toml::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?
+            }
+            _ => BTreeMap::new(),
+        };
+
+        Ok(Cheats { path, saved, rom: rom_hash(program), ..Cheats::default() })
+    }
+
+    // Another program is running, the cheats of the previous one are off
+    pub fn switch_program(&mut self, program: &[u8]) {
+        self.rom = rom_hash(program);
+        self.frozen.clear();
+        self.search = None;
+    }
+
+    // The cheats saved for the running program
+    pub fn saved(&self) -> &[Cheat] {
+        self.saved.get(&self.rom).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    // Turns on the saved cheat called `name`
+    pub fn enable(&mut self, name: &str, emulator: &mut Chip8) -> Result<()> {
+        let cheat = self
+            .saved()
+            .iter()
+            .find(|cheat| cheat.name == name)
+            .cloned()
+            .ok_or_else(|| CheatError(format!("No cheat called '{}' for this program", name)))?;
+        self.activate(&cheat, emulator);
+        Ok(())
+    }
+
+    // Pokes the value of `cheat`, and keeps writing it if it is frozen
+    pub fn activate(&mut self, cheat: &Cheat, emulator: &mut Chip8) {
+        cheat.target.write(emulator, cheat.value);
+        if cheat.freeze {
+            self.frozen.insert(cheat.target, cheat.value);
+        }
+    }
+
+    // Releases a frozen value, or all of them
+    pub fn unfreeze(&mut self, target: Option<Target>) {
+        match target {
+            Some(target) => {
+                self.frozen.remove(&target);
+            }
+            None => self.frozen.clear(),
+        }
+    }
+
+    // Saves `cheat` for the running program, replacing the one of the same
+    // name
+    pub fn save(&mut self, cheat: Cheat) -> Result<()> {
+        let path = self.path.clone().ok_or("No configuration directory to save cheats in")?;
+
+        let cheats = self.saved.entry(self.rom.clone()).or_default();
+        cheats.retain(|saved| saved.name != cheat.name);
+        cheats.push(cheat);
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, toml::to_string(&self.saved)?).map_err(|err| format!("Cannot write {}: {}", path.display(), err))?;
+        Ok(())
+    }
+
+    // Writes the frozen values, before every frame
+    pub fn apply(&self, emulator: &mut Chip8) {
+        for (target, value) in &self.frozen {
+            target.write(emulator, *value);
+        }
+    }
+
+    // Starts a search over all the memory and registers, or narrows the
+    // current one down, and describes what is left
+    pub fn search(&mut self, emulator: &Chip8, comparison: Option<Comparison>) -> std::result::Result<String, CheatError> {
+        let candidates: Vec<(Target, u8)> = match (self.search.take(), comparison) {
+            (Some(candidates), Some(comparison)) => candidates
+                .into_iter()
+                .filter(|(target, previous)| comparison.keeps(*previous, target.read(emulator)))
+                .collect(),
+            // a first search keeps everything, or what equals a value, as
+            // there is nothing to compare with yet
+            (None, Some(Comparison::Changed | Comparison::Same | Comparison::Up | Comparison::Down)) => {
+                return Err(CheatError(String::from("no search running, start one with search or search =VALUE")));
+            }
+            (_, comparison) => (0..MEMORY_SIZE as u16)
+                .map(Target::Memory)
+                .chain((0..REGISTERS_COUNT as u8).map(Target::Register))
+                .map(|target| (target, 0))
+                .filter(|(target, previous)| comparison.is_none_or(|comparison| comparison.keeps(*previous, target.read(emulator))))
+                .collect(),
+        };
+        let candidates: Vec<(Target, u8)> =
+            candidates.into_iter().map(|(target, _)| (target, target.read(emulator))).collect();
+
+        let mut text = format!("{} matches", candidates.len());
+        if !candidates.is_empty() && candidates.len() <= MATCHES_SHOWN {
+            let matches: Vec<String> = candidates.iter().map(|(target, value)| format!("{}={:02X}", target, value)).collect();
+            text += &format!(": {}", matches.join(" "));
+        }
+        self.search = Some(candidates);
+        Ok(text)
+    }
+}
@@ -4,10 +4,12 @@ use std::{error::Error, fmt, fs::OpenOptions, io::Read, path::PathBuf};
 use rand::random;
 
 use crate::opcodes::Opcodes;
+use crate::quirks::{QuirkPreset, Quirks};
 use crate::stack::{Stack, StackError};
+use crate::tracer::Tracer;
 use crate::utilities::{get_registers, get_register_and_value};
 
-const SPRITE_CHARS: [[u8; 5]; 0x10] = [
+pub const SPRITE_CHARS: [[u8; 5]; 0x10] = [
     [0xF0, 0x90, 0x90, 0x90, 0xF0], // 0
     [0x20, 0x60, 0x20, 0x20, 0x70], // 1
     [0xF0, 0x10, 0xF0, 0x80, 0xF0], // 2
@@ -25,7 +27,7 @@ const SPRITE_CHARS: [[u8; 5]; 0x10] = [
     [0xF0, 0x80, 0xF0, 0x80, 0xF0], // E
     [0xF0, 0x80, 0xF0, 0x80, 0x80], // F
 ];
-const SPRITE_CHARS_ADDR: u16 = 0x0000;
+pub const SPRITE_CHARS_ADDR: u16 = 0x0000;
 
 pub const MEMORY_SIZE: usize = 4086;
 pub const STACK_SIZE: usize = 16;
@@ -40,6 +42,10 @@ pub const LOADING_POINT: usize = 0x200;
 
 pub const FRAME_TIME: isize = 16666; // this is in microseconds
 
+// Start of a save state and version of its layout, bumped when it changes
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+const STATE_VERSION: u8 = 1;
+
 #[derive(Debug, Clone)]
 pub struct Chip8 {
     // Whole memory of the CHIP-8
@@ -62,7 +68,11 @@ pub struct Chip8 {
     delay_timer: u8,
 
     sound_timer: u8,
-    run_sound: bool,
+
+    // whether the buzzer sounds (the sound timer is running), and its
+    // changes not yet taken by the frontend
+    buzzer: bool,
+    buzzer_events: Vec<BuzzerEvent>,
 
     // the graphic screen
     gfx: [u8; SCREEN_WIDTH * SCREEN_HEIGTH / 8],
@@ -72,6 +82,26 @@ pub struct Chip8 {
 
     // time in seconds for executing operation
     time: isize,
+
+    // frames begun since power on
+    frames: u64,
+
+    // instructions executed since power on
+    instructions: u64,
+
+    // size of the loaded program, starting at LOADING_POINT
+    program_len: usize,
+
+    // behaviours of the interpreter the program expects
+    quirks: Quirks,
+}
+
+// The buzzer starting or stopping at `time`, in emulated microseconds since
+// power on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuzzerEvent {
+    pub time: u64,
+    pub on: bool,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -84,6 +114,16 @@ impl fmt::Display for LoadInMemoryError {
     }
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub struct StateError(&'static str);
+impl Error for StateError { }
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid save state: {}", self.0)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct PcOutOfMemoryBounds(u16);
 impl Error for PcOutOfMemoryBounds { }
@@ -120,10 +160,15 @@ impl Default for Chip8 {
             stack: Stack::<STACK_SIZE>::new(),
             delay_timer: 0,
             sound_timer: 0,
-            run_sound: false,
+            buzzer: false,
+            buzzer_events: Vec::new(),
             gfx: [0; SCREEN_WIDTH * SCREEN_HEIGTH / 8],
             keypad: 0,
             time: 0,
+            frames: 0,
+            instructions: 0,
+            program_len: 0,
+            quirks: QuirkPreset::default().quirks(),
         }
     }
 }
@@ -139,6 +184,27 @@ impl Chip8 {
     }
 
     pub fn cycle(&mut self, key: u16) -> Result<(), Box<dyn std::error::Error>> {
+        self.cycle_traced(key, &mut ())
+    }
+
+    // Same as `cycle`, reporting every executed instruction to `tracer`
+    pub fn cycle_traced(&mut self, key: u16, tracer: &mut dyn Tracer) -> Result<(), Box<dyn std::error::Error>> {
+        self.begin_frame(key);
+        tracer.frame_started(self);
+
+        // --- Execution of an instruction in a FRAME
+        while self.frame_pending() {
+            self.step_traced(tracer)?;
+        }
+
+        Ok(())
+    }
+
+    // Starts a new 60Hz frame: stores the pressed keys, updates the timers
+    // and gives the frame its time budget. `cycle` is `begin_frame` followed
+    // by `step` until the frame is no longer pending; debuggers drive the two
+    // halves themselves to stop in the middle of a frame.
+    pub fn begin_frame(&mut self, key: u16) {
         // store pressed key
         self.keypad = key;
 
@@ -147,34 +213,53 @@ impl Chip8 {
             self.delay_timer -= 1;
         }
 
-        self.run_sound = if self.sound_timer != 0 {
+        self.frames += 1;
+        self.time += FRAME_TIME;
+
+        if self.sound_timer > 0 {
             self.sound_timer -= 1;
-            true
-        } else {
-            false
-        };
+        }
+        self.update_buzzer();
+    }
 
-        // --- Execution of an instruction in a FRAME
-        self.time += FRAME_TIME;
-        while self.time > 0 {
-            if self.pc as usize > MEMORY_SIZE - 1 {
-                return Err(PcOutOfMemoryBounds(self.pc).into());
-            }
+    // Whether the current frame still has time left for more instructions
+    pub fn frame_pending(&self) -> bool {
+        self.time > 0
+    }
 
-            // get/fetch instruction
-            let instruction_bytes = self.get_instruction_bytes();
+    // Fetches, decodes and executes the instruction at `pc`, charging its
+    // overtime to the current frame. Returns the overtime.
+    pub fn step(&mut self) -> Result<isize, Box<dyn std::error::Error>> {
+        if self.pc as usize > MEMORY_SIZE - 2 {
+            return Err(PcOutOfMemoryBounds(self.pc).into());
+        }
 
-            // decode operation code of instruction
-            let instruction = Opcodes::try_from(instruction_bytes)?;
+        // get/fetch instruction
+        let instruction_bytes = self.get_instruction_bytes();
 
-            // execute instruction + get overtime that it takes to be executed originally
-            let overtime = self.execute_instruction(instruction, instruction_bytes)?;
+        // decode operation code of instruction
+        let instruction = Opcodes::try_from(instruction_bytes)?;
 
-            self.time -= overtime as isize;
+        // execute instruction + get overtime that it takes to be executed originally
+        let overtime = self.execute_instruction(instruction, instruction_bytes)?;
+
+        self.time -= overtime;
+        self.instructions += 1;
+
+        // the rest of the frame is spent waiting for the display
+        if instruction == Opcodes::DrawSprite && self.quirks.display_wait {
+            self.time = self.time.min(0);
         }
 
+        Ok(overtime)
+    }
+
+    pub fn step_traced(&mut self, tracer: &mut dyn Tracer) -> Result<isize, Box<dyn std::error::Error>> {
+        tracer.before_step(self);
+        let overtime = self.step()?;
+        tracer.after_step(self, overtime);
 
-        Ok(())
+        Ok(overtime)
     }
 
     fn get_instruction_bytes(&self) -> u16 {
@@ -182,24 +267,46 @@ impl Chip8 {
             | self.memory[self.pc as usize + 1] as u16
     }
 
-    pub fn tone(&mut self) -> bool {
-        self.run_sound
+    // Emulated time in microseconds since power on, the start of the
+    // current frame plus the time its instructions took so far
+    pub fn emulated_time(&self) -> u64 {
+        let frame_start = self.frames.saturating_sub(1) * FRAME_TIME as u64;
+        frame_start + (FRAME_TIME - self.time).clamp(0, FRAME_TIME) as u64
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    // The buzzer changes since the last call, oldest first
+    pub fn take_buzzer_events(&mut self) -> Vec<BuzzerEvent> {
+        std::mem::take(&mut self.buzzer_events)
+    }
+
+    // Records a buzzer change after the sound timer was written or counted
+    fn update_buzzer(&mut self) {
+        let on = self.sound_timer > 0;
+        if on != self.buzzer {
+            self.buzzer = on;
+            self.buzzer_events.push(BuzzerEvent { time: self.emulated_time(), on });
+        }
     }
 
     fn load_program_in_memory (&mut self, program: PathBuf) -> Result<(), LoadInMemoryError> {
         let mut file = OpenOptions::new()
             .read(true)
             .open(program)
-            .expect("File not found");
+            .map_err(|_| LoadInMemoryError("File not found"))?;
 
         let mut program_bytes : Vec<u8> = Vec::new();
-        file.read_to_end(&mut program_bytes).expect("Error in reading into vector");
+        file.read_to_end(&mut program_bytes).map_err(|_| LoadInMemoryError("Error in reading into vector"))?;
 
         if program_bytes.len() > MEMORY_SIZE - LOADING_POINT {
             return Err(LoadInMemoryError("Program is larger!"));
         }
 
         self.memory[LOADING_POINT..program_bytes.len() + LOADING_POINT].clone_from_slice(&program_bytes);
+        self.program_len = program_bytes.len();
 
         Ok(())
     }
@@ -308,6 +415,9 @@ impl Chip8 {
                 let (reg_x_idx, reg_y_idx) = get_registers(instruction_bytes)?;
 
                 self.registers[reg_x_idx] |= self.registers[reg_y_idx];
+                if self.quirks.vf_reset {
+                    self.registers[CARRY_REGISTER_IDX] = 0;
+                }
 
                 self.pc += 2;
                 return Ok(200);
@@ -317,6 +427,9 @@ impl Chip8 {
                 let (reg_x_idx, reg_y_idx) = get_registers(instruction_bytes)?;
 
                 self.registers[reg_x_idx] &= self.registers[reg_y_idx];
+                if self.quirks.vf_reset {
+                    self.registers[CARRY_REGISTER_IDX] = 0;
+                }
 
                 self.pc += 2;
                 return Ok(200);
@@ -326,6 +439,9 @@ impl Chip8 {
                 let (reg_x_idx, reg_y_idx) = get_registers(instruction_bytes)?;
 
                 self.registers[reg_x_idx] ^= self.registers[reg_y_idx];
+                if self.quirks.vf_reset {
+                    self.registers[CARRY_REGISTER_IDX] = 0;
+                }
 
                 self.pc += 2;
                 return Ok(200);
@@ -368,8 +484,9 @@ impl Chip8 {
             Opcodes::StoreRegInRegShiftRight => {
                 let (reg_x_idx, reg_y_idx) = get_registers(instruction_bytes)?;
 
-                self.registers[CARRY_REGISTER_IDX] = self.registers[reg_y_idx] & 0b00000001;
-                self.registers[reg_x_idx] = self.registers[reg_y_idx].wrapping_shr(1);
+                let source = if self.quirks.shift_vx { reg_x_idx } else { reg_y_idx };
+                self.registers[CARRY_REGISTER_IDX] = self.registers[source] & 0b00000001;
+                self.registers[reg_x_idx] = self.registers[source].wrapping_shr(1);
 
                 self.pc += 2;
                 return Ok(200);
@@ -395,8 +512,10 @@ impl Chip8 {
             Opcodes::StoreRegInRegShiftLeft => {
                 let (reg_x_idx, reg_y_idx) = get_registers(instruction_bytes)?;
 
-                self.registers[CARRY_REGISTER_IDX] = (self.registers[reg_x_idx] & 0b10000000) >> 7;
-                self.registers[reg_x_idx] = self.registers[reg_y_idx].wrapping_shl(1);
+                let source = if self.quirks.shift_vx { reg_x_idx } else { reg_y_idx };
+                let carry = if self.quirks.shift_carry_vx { reg_x_idx } else { source };
+                self.registers[CARRY_REGISTER_IDX] = (self.registers[carry] & 0b10000000) >> 7;
+                self.registers[reg_x_idx] = self.registers[source].wrapping_shl(1);
 
                 self.pc += 2;
                 return Ok(200);
@@ -424,7 +543,8 @@ impl Chip8 {
 
             Opcodes::JumpToAddr => {
                 let val = instruction_bytes & 0x0FFF;
-                self.pc = val + self.registers[0] as u16;
+                let reg_idx = self.quirks.jump_register(instruction_bytes);
+                self.pc = val + self.registers[reg_idx] as u16;
                 return Ok(105);
             }
             
@@ -449,14 +569,20 @@ impl Chip8 {
                 let col_a = pos_x as usize / 8;
                 let col_b = (col_a + 1) % (SCREEN_WIDTH / 8);
                 let mut collision = 0;
+                // clipped sprites lose the rows below the screen and the
+                // part right of it
+                let clipping = self.quirks.clipping;
                 for i in 0..(height as usize) {
+                    if clipping && pos_y as usize + i >= SCREEN_HEIGTH {
+                        break;
+                    }
                     let byte = self.memory[self.i as usize + i];
                     let y = (pos_y as usize + i) % SCREEN_HEIGTH;
                     let a = byte >> shift;
                     let fb_a = &mut gfx[y * SCREEN_WIDTH / 8 + col_a];
                     collision |= *fb_a & a;
                     *fb_a ^= a;
-                    if shift != 0 {
+                    if shift != 0 && !(clipping && col_b == 0) {
                         let b = byte << (8 - shift);
                         let fb_b = &mut gfx[y * SCREEN_WIDTH / 8 + col_b];
                         collision |= *fb_b & b;
@@ -526,6 +652,7 @@ impl Chip8 {
             Opcodes::SetSoundTimer => {
                 let (reg_idx, _) = get_register_and_value(instruction_bytes)?;
                 self.sound_timer = self.registers[reg_idx];
+                self.update_buzzer();
                 self.pc += 2;
                 return Ok(45);
             }
@@ -567,6 +694,9 @@ impl Chip8 {
                 for i in 0..reg_idx + 1 {
                     self.memory[self.i as usize + i] = self.registers[i];
                 }
+                if self.quirks.memory_increment {
+                    self.i += reg_idx as u16 + 1;
+                }
 
                 self.pc += 2;
                 return Ok(605);
@@ -578,6 +708,9 @@ impl Chip8 {
                 for i in 0..reg_idx + 1 {
                     self.registers[i] = self.memory[self.i as usize + i]
                 }
+                if self.quirks.memory_increment {
+                    self.i += reg_idx as u16 + 1;
+                }
                 
                 self.pc += 2;
                 return Ok(605);
@@ -589,6 +722,139 @@ impl Chip8 {
         self.gfx
     }
 
+    ////// Machine state access, used by the debugging front-ends
+    pub fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8; MEMORY_SIZE] {
+        &mut self.memory
+    }
+
+    pub fn registers(&self) -> &[u8; REGISTERS_COUNT] {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut [u8; REGISTERS_COUNT] {
+        &mut self.registers
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, value: u16) {
+        self.i = value;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn program_len(&self) -> usize {
+        self.program_len
+    }
+
+    // The program where it was loaded, as the file was until it runs and
+    // writes over itself
+    pub fn program(&self) -> &[u8] {
+        &self.memory[LOADING_POINT..LOADING_POINT + self.program_len]
+    }
+
+    pub fn stack(&self) -> &Stack<STACK_SIZE> {
+        &self.stack
+    }
+
+    pub fn keypad(&self) -> u16 {
+        self.keypad
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+        self.update_buzzer();
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    // The whole machine as bytes, for save states. The quirks are settings
+    // rather than state and are left out.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        state.extend_from_slice(STATE_MAGIC);
+        state.push(STATE_VERSION);
+        state.extend_from_slice(&self.memory);
+        state.extend_from_slice(&self.registers);
+        state.extend_from_slice(&self.i.to_le_bytes());
+        state.extend_from_slice(&self.pc.to_le_bytes());
+        state.push(self.stack.as_slice().len() as u8);
+        for value in self.stack.as_slice() {
+            state.extend_from_slice(&value.to_le_bytes());
+        }
+        state.push(self.delay_timer);
+        state.push(self.sound_timer);
+        state.extend_from_slice(&self.gfx);
+        state.extend_from_slice(&self.keypad.to_le_bytes());
+        state.extend_from_slice(&(self.time as i64).to_le_bytes());
+        state.extend_from_slice(&self.frames.to_le_bytes());
+        state.extend_from_slice(&self.instructions.to_le_bytes());
+        state.extend_from_slice(&(self.program_len as u32).to_le_bytes());
+        state
+    }
+
+    // Restores a machine saved by `save_state`, keeping the current quirks
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader(state);
+        if reader.take(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(StateError("not a CHIP-8 save state"));
+        }
+        if reader.u8()? != STATE_VERSION {
+            return Err(StateError("saved by another version"));
+        }
+
+        let mut machine = Chip8 { quirks: self.quirks, ..Default::default() };
+        machine.memory.copy_from_slice(reader.take(MEMORY_SIZE)?);
+        machine.registers.copy_from_slice(reader.take(REGISTERS_COUNT)?);
+        machine.i = reader.u16()?;
+        machine.pc = reader.u16()?;
+        let depth = reader.u8()?;
+        let stack = (0..depth).map(|_| reader.u16()).collect::<Result<Vec<u16>, StateError>>()?;
+        machine.stack = Stack::from_slice(&stack).map_err(|_| StateError("stack too deep"))?;
+        machine.delay_timer = reader.u8()?;
+        machine.sound_timer = reader.u8()?;
+        let gfx_len = machine.gfx.len();
+        machine.gfx.copy_from_slice(reader.take(gfx_len)?);
+        machine.keypad = reader.u16()?;
+        machine.time = i64::from_le_bytes(reader.array()?) as isize;
+        machine.frames = u64::from_le_bytes(reader.array()?);
+        machine.instructions = u64::from_le_bytes(reader.array()?);
+        machine.program_len = u32::from_le_bytes(reader.array()?) as usize;
+        if !reader.0.is_empty() {
+            return Err(StateError("unexpected data at the end"));
+        }
+
+        machine.buzzer = machine.sound_timer > 0;
+        *self = machine;
+        Ok(())
+    }
+
     fn load_font_set_in_memory(&mut self) {
         for (i, sprite) in SPRITE_CHARS.iter().enumerate() {
             let p = SPRITE_CHARS_ADDR as usize + i * sprite.len();
@@ -596,3 +862,29 @@ impl Chip8 {
         }
     }
 }
+
+// Reads the fields of a save state in order
+struct StateReader<'a>(&'a [u8]);
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], StateError> {
+        if self.0.len() < count {
+            return Err(StateError("truncated"));
+        }
+        let (taken, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+}
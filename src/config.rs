@@ -0,0 +1,415 @@
+// Settings resolved from layers of configuration
+//
+// Every setting comes from the last of these layers that sets it:
+//   1. the built-in defaults
+//   2. the user configuration file, chip-8/config.toml in the XDG config
+//      directory (usually ~/.config), or the file given with `--config`
+//   3. the entry of the program in the ROM database
+//   4. the command line
+// `--print-config` shows the resolved settings in the format of the file:
+//
+//   frontend = "window"      # or "tui" for the terminal debugger
+//
+//   [display]
+//   scale = 16
+//   scaling = "integer"
+//   fullscreen = false
+//   palette = "mono"
+//   persistence = "fade:30"
+//   filter = "none"
+//   osd = false
+//
+//   [emulation]
+//   quirks = "classic"
+//   speed = 1.0
+//
+//   [audio]
+//   tone = 440.0
+//   waveform = "square"
+//   volume = 0.25
+//   mute = false
+//
+//   [keys]                   # CHIP-8 key = keyboard key
+//   0 = "X"
+//   1 = "1"
+//   ...
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{BuzzerSettings, Waveform};
+use crate::filters::Filter;
+use crate::keymap::Keymap;
+use crate::palette::Palette;
+use crate::quirks::QuirkPreset;
+use crate::render::{Persistence, Scaling};
+use crate::romdb::RomInfo;
+use crate::{DisplaySettings, EmulationSettings, Result, SCALE};
+
+// The user configuration file, in the XDG config directory
+const CONFIG_DIRECTORY: &str = "chip-8";
+const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrontendKind {
+    // the game in a window
+    #[default]
+    Window,
+    // the terminal debugger next to the window
+    Tui,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct FrontendKindError(String);
+impl std::error::Error for FrontendKindError { }
+
+impl fmt::Display for FrontendKindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid frontend: '{}', expected window or tui", self.0)
+    }
+}
+
+impl FromStr for FrontendKind {
+    type Err = FrontendKindError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "window" => Ok(FrontendKind::Window),
+            "tui" => Ok(FrontendKind::Tui),
+            _ => Err(FrontendKindError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for FrontendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrontendKind::Window => write!(f, "window"),
+            FrontendKind::Tui => write!(f, "tui"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigError(String);
+impl std::error::Error for ConfigError { }
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration: {}", self.0)
+    }
+}
+
+// The settings of one layer, only the ones given override the layers below
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigLayer {
+    #[serde(with = "text")]
+    pub frontend: Option<FrontendKind>,
+    pub display: DisplayLayer,
+    pub emulation: EmulationLayer,
+    pub audio: AudioLayer,
+    // CHIP-8 key, as a hex digit = name of the keyboard key
+    pub keys: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayLayer {
+    pub scale: Option<u32>,
+    #[serde(with = "text")]
+    pub scaling: Option<Scaling>,
+    pub fullscreen: Option<bool>,
+    #[serde(with = "text")]
+    pub palette: Option<Palette>,
+    #[serde(with = "text")]
+    pub persistence: Option<Persistence>,
+    #[serde(with = "text")]
+    pub filter: Option<Filter>,
+    pub osd: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmulationLayer {
+    #[serde(with = "text")]
+    pub quirks: Option<QuirkPreset>,
+    pub speed: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioLayer {
+    pub tone: Option<f32>,
+    #[serde(with = "text")]
+    pub waveform: Option<Waveform>,
+    pub volume: Option<f32>,
+    pub mute: Option<bool>,
+}
+
+// The settings parsed from text on the command line, such as palettes, are
+// written the same way in the file
+mod text {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| text.parse().map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
+impl ConfigLayer {
+    // The sections that set anything, to show what a layer changes
+    pub fn table(&self) -> Result<toml::Table> {
+        let mut table = toml::Table::try_from(self)?;
+        table.retain(|_, value| value.as_table().is_none_or(|section| !section.is_empty()));
+        Ok(table)
+    }
+}
+
+// Settings once every layer is applied
+pub struct Config {
+    pub frontend: FrontendKind,
+    pub display: DisplaySettings,
+    pub emulation: EmulationSettings,
+    pub buzzer: BuzzerSettings,
+    pub keymap: Keymap,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            frontend: FrontendKind::default(),
+            display: DisplaySettings {
+                scale: SCALE,
+                scaling: Scaling::default(),
+                fullscreen: false,
+                palette: Palette::default(),
+                persistence: Persistence::default(),
+                filter: Filter::default(),
+                osd: false,
+            },
+            emulation: EmulationSettings {
+                quirks: QuirkPreset::default(),
+                speed: 1.0,
+            },
+            buzzer: BuzzerSettings::default(),
+            keymap: Keymap::default(),
+        }
+    }
+}
+
+impl Config {
+    // Overrides the settings `layer` gives
+    fn apply(&mut self, layer: &ConfigLayer) -> Result<()> {
+        let ConfigLayer { frontend, display, emulation, audio, keys } = layer;
+
+        set(&mut self.frontend, frontend);
+
+        if let Some(scale) = display.scale {
+            if !(1..=64).contains(&scale) {
+                return Err(ConfigError(format!("scale {} is not between 1 and 64", scale)).into());
+            }
+        }
+        set(&mut self.display.scale, &display.scale);
+        set(&mut self.display.scaling, &display.scaling);
+        set(&mut self.display.fullscreen, &display.fullscreen);
+        set(&mut self.display.palette, &display.palette);
+        set(&mut self.display.persistence, &display.persistence);
+        set(&mut self.display.filter, &display.filter);
+        set(&mut self.display.osd, &display.osd);
+
+        if let Some(speed) = emulation.speed {
+            check_speed(speed).map_err(ConfigError)?;
+        }
+        set(&mut self.emulation.quirks, &emulation.quirks);
+        set(&mut self.emulation.speed, &emulation.speed);
+
+        if let Some(tone) = audio.tone {
+            check_tone(tone).map_err(ConfigError)?;
+        }
+        set(&mut self.buzzer.frequency, &audio.tone);
+        set(&mut self.buzzer.waveform, &audio.waveform);
+        if let Some(volume) = audio.volume {
+            check_volume(volume).map_err(ConfigError)?;
+        }
+        set(&mut self.buzzer.volume, &audio.volume);
+        set(&mut self.buzzer.muted, &audio.mute);
+
+        for (key, name) in keys {
+            self.keymap.bind(key, name)?;
+        }
+        Ok(())
+    }
+
+    // A layer giving every setting
+    fn layer(&self) -> ConfigLayer {
+        ConfigLayer {
+            frontend: Some(self.frontend),
+            display: DisplayLayer {
+                scale: Some(self.display.scale),
+                scaling: Some(self.display.scaling),
+                fullscreen: Some(self.display.fullscreen),
+                palette: Some(self.display.palette),
+                persistence: Some(self.display.persistence),
+                filter: Some(self.display.filter),
+                osd: Some(self.display.osd),
+            },
+            emulation: EmulationLayer {
+                quirks: Some(self.emulation.quirks),
+                speed: Some(self.emulation.speed),
+            },
+            audio: AudioLayer {
+                tone: Some(self.buzzer.frequency),
+                waveform: Some(self.buzzer.waveform),
+                volume: Some(self.buzzer.volume),
+                mute: Some(self.buzzer.muted),
+            },
+            keys: self.keymap.bindings(),
+        }
+    }
+}
+
+fn set<T: Copy>(setting: &mut T, value: &Option<T>) {
+    if let Some(value) = value {
+        *setting = *value;
+    }
+}
+
+// The layers over the defaults, but for the ROM database entry which is only
+// known once a program is picked
+pub struct ConfigLayers {
+    // the user configuration file, if there is one
+    user: Option<(PathBuf, ConfigLayer)>,
+    // the command line, or whatever else comes last, and its name
+    overrides: (String, ConfigLayer),
+}
+
+impl ConfigLayers {
+    // `file` is read instead of the user configuration file, it has to exist
+    pub fn load(file: Option<&Path>, command_line: ConfigLayer) -> Result<Self> {
+        let path = match file {
+            Some(path) => Some(path.to_path_buf()),
+            None => user_config_path().filter(|path| path.exists()),
+        };
+        let user = match path {
+            Some(path) => {
+                let layer = read_layer(&path)?;
+                Some((path, layer))
+            }
+            None => None,
+        };
+
+        Ok(ConfigLayers { user, overrides: ("command line".to_string(), command_line) })
+    }
+
+    // The defaults and the ROM database with `layer` on top, but not the
+    // user configuration file, for settings that are the same everywhere
+    pub fn without_user_file(name: &str, layer: ConfigLayer) -> Self {
+        ConfigLayers { user: None, overrides: (name.to_string(), layer) }
+    }
+
+    // The settings for a program, `rom` being its entry in the ROM database
+    pub fn resolve(&self, rom: Option<&RomInfo>) -> Result<Config> {
+        let mut config = Config::default();
+        for (source, layer) in self.layers(rom) {
+            config.apply(layer).map_err(|err| format!("{}: {}", source, err))?;
+        }
+        Ok(config)
+    }
+
+    // The resolved settings as a configuration file, with the layers they
+    // come from
+    pub fn print(&self, rom: Option<&RomInfo>) -> Result<String> {
+        let config = self.resolve(rom)?;
+        let sources: Vec<String> = self.layers(rom).into_iter().map(|(source, _)| source).collect();
+
+        let mut text = String::from("# defaults");
+        for source in sources {
+            text += &format!(" < {}", source);
+        }
+        text += "\n\n";
+        text += &toml::to_string(&config.layer())?;
+        Ok(text)
+    }
+
+    // The layers that set anything, from the lowest priority, with where
+    // they come from
+    fn layers<'a>(&'a self, rom: Option<&'a RomInfo>) -> Vec<(String, &'a ConfigLayer)> {
+        let mut layers = Vec::new();
+        if let Some((path, layer)) = &self.user {
+            layers.push((path.display().to_string(), layer));
+        }
+        if let Some(rom) = rom {
+            layers.push((format!("ROM database ({})", rom.title), &rom.config));
+        }
+        let (name, overrides) = &self.overrides;
+        layers.push((name.clone(), overrides));
+
+        layers.retain(|(_, layer)| **layer != ConfigLayer::default());
+        layers
+    }
+}
+
+// <XDG config directory>/chip-8, where the files of the user are kept
+pub fn user_directory() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(CONFIG_DIRECTORY))
+}
+
+// <XDG config directory>/chip-8/config.toml
+pub fn user_config_path() -> Option<PathBuf> {
+    user_directory().map(|dir| dir.join(CONFIG_FILE))
+}
+
+fn read_layer(path: &Path) -> Result<ConfigLayer> {
+    let text = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+    let layer = toml::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(layer)
+}
+
+// The pitches of the buzzer, in Hz, as far as the ear goes
+pub const MIN_TONE: f32 = 20.0;
+pub const MAX_TONE: f32 = 20_000.0;
+
+pub fn check_tone(tone: f32) -> std::result::Result<f32, String> {
+    if !(MIN_TONE..=MAX_TONE).contains(&tone) {
+        return Err(format!("tone {} is not between {} and {} Hz", tone, MIN_TONE, MAX_TONE));
+    }
+    Ok(tone)
+}
+
+pub fn check_volume(volume: f32) -> std::result::Result<f32, String> {
+    if !(0.0..=1.0).contains(&volume) {
+        return Err(format!("volume {} is not between 0.0 and 1.0", volume));
+    }
+    Ok(volume)
+}
+
+// Speeds are multiples of the real CHIP-8 speed
+pub fn check_speed(speed: f64) -> std::result::Result<f64, String> {
+    if !(0.1..=16.0).contains(&speed) {
+        return Err(format!("speed {} is not between 0.1 and 16", speed));
+    }
+    Ok(speed)
+}
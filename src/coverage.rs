@@ -0,0 +1,280 @@
+// ROM code coverage
+//
+// Records which addresses were executed and, for every skip instruction
+// (3xkk, 4xkk, 5xy0, 9xy0, Ex9E, ExA1), how many times the skip was taken
+// or not, and where every Bnnn jumped to. Exported on exit as an annotated
+// disassembly listing (.lst) and a JSON summary (.json) that ROM test suites
+// can check and `cfg` reads the jumps from.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::chip8::{Chip8, LOADING_POINT, MEMORY_SIZE};
+use crate::disasm::{disassemble, instruction_at};
+use crate::opcodes::Opcodes;
+use crate::tracer::Tracer;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct SkipOutcomes {
+    taken: u64,
+    not_taken: u64,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    program_size: usize,
+    executed_bytes: usize,
+    executed_instructions: usize,
+    byte_coverage: f64,
+    skip_instructions: usize,
+    skip_outcomes_covered: usize,
+    skip_outcome_coverage: f64,
+    executed: Vec<ExecutedAddress>,
+    skips: Vec<SkipReport>,
+    indirect_jumps: Vec<IndirectJump>,
+}
+
+#[derive(Serialize)]
+struct ExecutedAddress {
+    address: u16,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct SkipReport {
+    address: u16,
+    instruction: String,
+    taken: u64,
+    not_taken: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndirectJump {
+    address: u16,
+    targets: BTreeSet<u16>,
+}
+
+// What `cfg` reads from a summary
+#[derive(Deserialize)]
+struct Jumps {
+    #[serde(default)]
+    indirect_jumps: Vec<IndirectJump>,
+}
+
+pub struct Coverage {
+    // output path without extension
+    output: PathBuf,
+
+    // execution count per address
+    executed: Box<[u64; MEMORY_SIZE]>,
+    skips: BTreeMap<u16, SkipOutcomes>,
+    // the addresses every Bnnn jumped to
+    jumps: BTreeMap<u16, BTreeSet<u16>>,
+
+    // address and bytes of the instruction fetched in `before_step`
+    current: Option<(u16, u16)>,
+}
+
+// One line of the listing
+enum Item {
+    Instruction(u16),
+    Data(u8),
+}
+
+impl Coverage {
+    pub fn new(output: PathBuf) -> Self {
+        Coverage {
+            output,
+            executed: Box::new([0; MEMORY_SIZE]),
+            skips: BTreeMap::new(),
+            jumps: BTreeMap::new(),
+            current: None,
+        }
+    }
+
+    pub fn write_report(&self, emulator: &Chip8) -> io::Result<()> {
+        let items = self.walk(emulator.memory(), emulator.program_len());
+
+        self.write_listing(&items)?;
+
+        let file = File::create(self.output.with_extension("json"))?;
+        let summary = self.summary(&items, emulator.program_len());
+        serde_json::to_writer_pretty(BufWriter::new(file), &summary)?;
+
+        Ok(())
+    }
+
+    // Splits the program into instructions and data bytes. Unexecuted bytes
+    // are read as instructions too, except for a lone byte in front of code
+    // that was executed at an odd address.
+    fn walk(&self, memory: &[u8], program_len: usize) -> Vec<(usize, Item)> {
+        let end = (LOADING_POINT + program_len).min(MEMORY_SIZE);
+        let mut items = Vec::new();
+
+        let mut addr = LOADING_POINT;
+        while addr < end {
+            let odd_code_next = self.executed[addr] == 0 && addr + 1 < end && self.executed[addr + 1] != 0;
+
+            match instruction_at(memory, addr) {
+                Some(instruction_bytes) if addr + 1 < end && !odd_code_next => {
+                    items.push((addr, Item::Instruction(instruction_bytes)));
+                    addr += 2;
+                }
+                _ => {
+                    items.push((addr, Item::Data(memory[addr])));
+                    addr += 1;
+                }
+            }
+        }
+
+        items
+    }
+
+    // Annotated listing of the program: every line starts with the execution
+    // count (or dashes when never executed), skips get their outcomes.
+    fn write_listing(&self, items: &[(usize, Item)]) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(self.output.with_extension("lst"))?);
+
+        for (addr, item) in items {
+            let count = match self.executed[*addr] {
+                0 => String::from("-----"),
+                count => count.to_string(),
+            };
+
+            match item {
+                Item::Instruction(instruction_bytes) => {
+                    let line = format!("{:>10}  {:03X}  {:04X}  {}", count, addr, instruction_bytes, disassemble(*instruction_bytes));
+                    match self.skips.get(&(*addr as u16)) {
+                        Some(outcomes) => writeln!(out, "{:<42} ; skip taken {}, not taken {}", line, outcomes.taken, outcomes.not_taken)?,
+                        None if is_skip(*instruction_bytes) && self.executed[*addr] == 0 => writeln!(out, "{:<42} ; skip never executed", line)?,
+                        None => writeln!(out, "{}", line)?,
+                    }
+                }
+                Item::Data(byte) => writeln!(out, "{:>10}  {:03X}  {:02X}    DB   #{:02X}", count, addr, byte, byte)?,
+            }
+        }
+
+        out.flush()
+    }
+
+    fn summary(&self, items: &[(usize, Item)], program_len: usize) -> Summary {
+        let executed: Vec<ExecutedAddress> = items
+            .iter()
+            .filter(|(addr, _)| self.executed[*addr] != 0)
+            .map(|(addr, _)| ExecutedAddress { address: *addr as u16, count: self.executed[*addr] })
+            .collect();
+        let executed_bytes = items
+            .iter()
+            .filter(|(addr, _)| self.executed[*addr] != 0)
+            .map(|(_, item)| match item {
+                Item::Instruction(_) => 2,
+                Item::Data(_) => 1,
+            })
+            .sum();
+
+        // every skip instruction of the program counts, executed or not
+        let skips: Vec<SkipReport> = items
+            .iter()
+            .filter_map(|(addr, item)| match item {
+                Item::Instruction(instruction_bytes) if is_skip(*instruction_bytes) => {
+                    let outcomes = self.skips.get(&(*addr as u16)).copied().unwrap_or_default();
+                    Some(SkipReport {
+                        address: *addr as u16,
+                        instruction: disassemble(*instruction_bytes),
+                        taken: outcomes.taken,
+                        not_taken: outcomes.not_taken,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+
+        let skip_outcomes_covered = skips
+            .iter()
+            .map(|s| (s.taken > 0) as usize + (s.not_taken > 0) as usize)
+            .sum();
+
+        Summary {
+            program_size: program_len,
+            executed_bytes,
+            executed_instructions: executed.len(),
+            byte_coverage: ratio(executed_bytes, program_len),
+            skip_instructions: skips.len(),
+            skip_outcomes_covered,
+            skip_outcome_coverage: ratio(skip_outcomes_covered, skips.len() * 2),
+            executed,
+            skips,
+            indirect_jumps: self
+                .jumps
+                .iter()
+                .map(|(address, targets)| IndirectJump { address: *address, targets: targets.clone() })
+                .collect(),
+        }
+    }
+}
+
+impl Tracer for Coverage {
+    fn before_step(&mut self, emulator: &Chip8) {
+        let pc = emulator.pc();
+        self.current = instruction_at(emulator.memory(), pc as usize).map(|instruction_bytes| (pc, instruction_bytes));
+    }
+
+    fn after_step(&mut self, emulator: &Chip8, _overtime: isize) {
+        let Some((pc, instruction_bytes)) = self.current.take() else {
+            return;
+        };
+
+        self.executed[pc as usize] += 1;
+
+        if Opcodes::try_from(instruction_bytes) == Ok(Opcodes::JumpToAddr) {
+            self.jumps.entry(pc).or_default().insert(emulator.pc());
+        }
+
+        // a skip moves pc over the next instruction when taken
+        if is_skip(instruction_bytes) {
+            let outcomes = self.skips.entry(pc).or_default();
+            if emulator.pc() == pc + 4 {
+                outcomes.taken += 1;
+            } else {
+                outcomes.not_taken += 1;
+            }
+        }
+    }
+}
+
+// The targets of the Bnnn jumps recorded in a JSON summary, by address of
+// the jump
+pub fn read_indirect_jumps(path: &Path) -> crate::Result<BTreeMap<usize, BTreeSet<usize>>> {
+    let text = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+    let jumps: Jumps = serde_json::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    Ok(jumps
+        .indirect_jumps
+        .into_iter()
+        .map(|jump| (jump.address as usize, jump.targets.into_iter().map(usize::from).collect()))
+        .collect())
+}
+
+fn is_skip(instruction_bytes: u16) -> bool {
+    matches!(
+        Opcodes::try_from(instruction_bytes),
+        Ok(Opcodes::SkipIfEqualVal
+            | Opcodes::SkipIfNotEqualVal
+            | Opcodes::SkipIfEqualReg
+            | Opcodes::SkipIfNotEqualReg
+            | Opcodes::SkipIfPressed
+            | Opcodes::SkipIfNotPressed)
+    )
+}
+
+fn ratio(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
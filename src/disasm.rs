@@ -0,0 +1,142 @@
+// Disassembler turning 2 bytes instructions back into readable mnemonics
+// (the classic Cowgod's Chip-8 technical reference notation)
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::chip8::LOADING_POINT;
+use crate::opcodes::Opcodes;
+use crate::quirks::Quirks;
+
+// Column of the address and bytes comments in a listing
+const COMMENT_COLUMN: usize = 28;
+
+pub fn disassemble(instruction_bytes: u16) -> String {
+    disassemble_with(instruction_bytes, &Quirks::default())
+}
+
+// Disassembles Bnnn with the register `quirks` add to nnn
+pub fn disassemble_with(instruction_bytes: u16, quirks: &Quirks) -> String {
+    let x = (instruction_bytes >> 8) & 0x000F;
+    let y = (instruction_bytes >> 4) & 0x000F;
+    let n = instruction_bytes & 0x000F;
+    let kk = instruction_bytes & 0x00FF;
+    let nnn = instruction_bytes & 0x0FFF;
+
+    let Ok(instruction) = Opcodes::try_from(instruction_bytes) else {
+        return format!("DW   #{:04X}", instruction_bytes);
+    };
+
+    match instruction {
+        Opcodes::SysExecute => format!("SYS  #{:03X}", nnn),
+        Opcodes::ClearScreen => "CLS".to_string(),
+        Opcodes::Return => "RET".to_string(),
+        Opcodes::JumpTo => format!("JP   #{:03X}", nnn),
+        Opcodes::SubRoutineExecute => format!("CALL #{:03X}", nnn),
+        Opcodes::SkipIfEqualVal => format!("SE   V{:X}, #{:02X}", x, kk),
+        Opcodes::SkipIfNotEqualVal => format!("SNE  V{:X}, #{:02X}", x, kk),
+        Opcodes::SkipIfEqualReg => format!("SE   V{:X}, V{:X}", x, y),
+        Opcodes::StoreValInReg => format!("LD   V{:X}, #{:02X}", x, kk),
+        Opcodes::AddValToReg => format!("ADD  V{:X}, #{:02X}", x, kk),
+        Opcodes::StoreRegInReg => format!("LD   V{:X}, V{:X}", x, y),
+        Opcodes::ORReg => format!("OR   V{:X}, V{:X}", x, y),
+        Opcodes::ANDReg => format!("AND  V{:X}, V{:X}", x, y),
+        Opcodes::XORReg => format!("XOR  V{:X}, V{:X}", x, y),
+        Opcodes::AddRegToReg => format!("ADD  V{:X}, V{:X}", x, y),
+        Opcodes::SubRegFromReg => format!("SUB  V{:X}, V{:X}", x, y),
+        Opcodes::StoreRegInRegShiftRight => format!("SHR  V{:X}, V{:X}", x, y),
+        Opcodes::SetRegMinusReg => format!("SUBN V{:X}, V{:X}", x, y),
+        Opcodes::StoreRegInRegShiftLeft => format!("SHL  V{:X}, V{:X}", x, y),
+        Opcodes::SkipIfNotEqualReg => format!("SNE  V{:X}, V{:X}", x, y),
+        Opcodes::StoreMemoryInAddr => format!("LD   I, #{:03X}", nnn),
+        Opcodes::JumpToAddr => format!("JP   V{:X}, #{:03X}", quirks.jump_register(instruction_bytes), nnn),
+        Opcodes::SetRandomNum => format!("RND  V{:X}, #{:02X}", x, kk),
+        Opcodes::DrawSprite => format!("DRW  V{:X}, V{:X}, {}", x, y, n),
+        Opcodes::SkipIfPressed => format!("SKP  V{:X}", x),
+        Opcodes::SkipIfNotPressed => format!("SKNP V{:X}", x),
+        Opcodes::StoreDelayTimer => format!("LD   V{:X}, DT", x),
+        Opcodes::WaitKeypress => format!("LD   V{:X}, K", x),
+        Opcodes::SetDelayTimer => format!("LD   DT, V{:X}", x),
+        Opcodes::SetSoundTimer => format!("LD   ST, V{:X}", x),
+        Opcodes::AddValueToRegI => format!("ADD  I, V{:X}", x),
+        Opcodes::SetIRegToStripeAddr => format!("LD   F, V{:X}", x),
+        Opcodes::StoreBCD => format!("LD   B, V{:X}", x),
+        Opcodes::StoreRegsInMemoryFromRegI => format!("LD   [I], V{:X}", x),
+        Opcodes::LoadRegsInMemoryFromRegI => format!("LD   V{:X}, [I]", x),
+    }
+}
+
+// Disassembles the instruction stored at `addr` in `memory`
+pub fn disassemble_at(memory: &[u8], addr: usize) -> String {
+    match instruction_at(memory, addr) {
+        Some(instruction_bytes) => disassemble(instruction_bytes),
+        None => "??".to_string(),
+    }
+}
+
+pub fn instruction_at(memory: &[u8], addr: usize) -> Option<u16> {
+    if addr + 1 >= memory.len() {
+        return None;
+    }
+    Some(((memory[addr] as u16) << 8) | memory[addr + 1] as u16)
+}
+
+// A listing of `program`, every word from #200 disassembled as an
+// instruction, that the assembler turns back into the same bytes. Jump and
+// call targets get labels, addresses and bytes are in comments:
+//     L20A:
+//         LD   V0, #0A            ; 20A  600A
+// Bnnn jumps are written with the register `quirks` add to nnn.
+pub fn listing(program: &[u8], quirks: &Quirks) -> String {
+    let words: Vec<(usize, u16)> = program
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(idx, bytes)| (LOADING_POINT + 2 * idx, u16::from_be_bytes([bytes[0], bytes[1]])))
+        .collect();
+
+    // the targets that are words of the listing
+    let end = LOADING_POINT + program.len();
+    let labels: BTreeSet<usize> = words
+        .iter()
+        .filter_map(|(_, instruction_bytes)| jump_target(*instruction_bytes))
+        .filter(|target| (LOADING_POINT..end - 1).contains(target) && (target - LOADING_POINT).is_multiple_of(2))
+        .collect();
+
+    let mut text = String::new();
+    for (addr, instruction_bytes) in &words {
+        if labels.contains(addr) {
+            let _ = writeln!(text, "L{:03X}:", addr);
+        }
+        let instruction = match jump_target(*instruction_bytes) {
+            Some(target) if labels.contains(&target) => {
+                let mnemonic = if instruction_bytes & 0xF000 == 0x1000 { "JP  " } else { "CALL" };
+                format!("{} L{:03X}", mnemonic, target)
+            }
+            // the core runs 5xyn and 9xyn whatever n is, the assembler
+            // only writes n = 0
+            _ if matches!(instruction_bytes & 0xF000, 0x5000 | 0x9000) && instruction_bytes & 0x000F != 0 => {
+                format!("DW   #{:04X}", instruction_bytes)
+            }
+            _ => disassemble_with(*instruction_bytes, quirks),
+        };
+        let _ = writeln!(text, "    {:<width$}; {:03X}  {:04X}", instruction, addr, instruction_bytes, width = COMMENT_COLUMN - 4);
+    }
+
+    // a last odd byte
+    if let [.., last] = program {
+        if program.len() % 2 == 1 {
+            let line = format!("DB   #{:02X}", last);
+            let _ = writeln!(text, "    {:<width$}; {:03X}  {:02X}", line, end - 1, last, width = COMMENT_COLUMN - 4);
+        }
+    }
+
+    text
+}
+
+// The address JP nnn and CALL nnn go to
+fn jump_target(instruction_bytes: u16) -> Option<usize> {
+    match Opcodes::try_from(instruction_bytes) {
+        Ok(Opcodes::JumpTo | Opcodes::SubRoutineExecute) => Some((instruction_bytes & 0x0FFF) as usize),
+        _ => None,
+    }
+}
@@ -0,0 +1,363 @@
+// Software CRT look for the presented screen
+//
+// The filters are applied on the CPU to the scaled up frame, before it is
+// uploaded to the SDL texture:
+//   scanlines  every row of CHIP-8 pixels gets darker towards its edges
+//   grid       a thin dark line between CHIP-8 pixels
+//   bloom      lit pixels glow onto their neighbours
+//   barrel     the picture is bent like the glass of a CRT
+// "crt" stands for scanlines, bloom and barrel together. Work is done per
+// CHIP-8 pixel, and every row of the frame is either built once or copied
+// from one as bright, so that it stays cheap at the default scale of 16 even
+// in unoptimised builds.
+
+use std::fmt;
+use std::str::FromStr;
+
+// Brightness (out of 256) of the edges of a scanline and of the grid lines
+const SCANLINE_EDGE: u32 = 96;
+const GRID_LINE: u32 = 140;
+
+// Strength (out of 256) of the glow added around lit pixels
+const BLOOM_STRENGTH: u32 = 320;
+
+// How far the corners are pulled in by the barrel distortion
+const BARREL_CURVATURE: f32 = 0.08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Filter {
+    pub scanlines: bool,
+    pub grid: bool,
+    pub bloom: bool,
+    pub barrel: bool,
+}
+
+const NONE: Filter = Filter { scanlines: false, grid: false, bloom: false, barrel: false };
+
+// Filters cycled through at runtime, in order
+pub const PRESETS: [Filter; 5] = [
+    NONE,
+    Filter { scanlines: true, ..NONE },
+    Filter { grid: true, ..NONE },
+    Filter { bloom: true, ..NONE },
+    Filter { scanlines: true, bloom: true, barrel: true, ..NONE },
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct FilterError(String);
+impl std::error::Error for FilterError { }
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid filter: {}", self.0)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    // A comma separated list of filters, e.g. "scanlines,grid"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = NONE;
+        for name in s.split(',').map(str::trim) {
+            match name {
+                "none" => {}
+                "scanlines" => filter.scanlines = true,
+                "grid" => filter.grid = true,
+                "bloom" => filter.bloom = true,
+                "barrel" => filter.barrel = true,
+                "crt" => {
+                    filter.scanlines = true;
+                    filter.bloom = true;
+                    filter.barrel = true;
+                }
+                _ => {
+                    return Err(FilterError(format!(
+                        "'{}', expected none, scanlines, grid, bloom, barrel or crt",
+                        name
+                    )))
+                }
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = [
+            (self.scanlines, "scanlines"),
+            (self.grid, "grid"),
+            (self.bloom, "bloom"),
+            (self.barrel, "barrel"),
+        ]
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, name)| *name)
+        .collect();
+
+        if names.is_empty() {
+            write!(f, "none")
+        }
+        else {
+            write!(f, "{}", names.join(","))
+        }
+    }
+}
+
+impl Filter {
+    pub fn is_none(&self) -> bool {
+        *self == NONE
+    }
+
+    // The preset following this filter, for cycling at runtime
+    pub fn next(&self) -> Filter {
+        match PRESETS.iter().position(|preset| preset == self) {
+            Some(idx) => PRESETS[(idx + 1) % PRESETS.len()],
+            None => PRESETS[0],
+        }
+    }
+}
+
+// Applies filters to frames of `resolution` scaled up by `scale`, keeping
+// the buffers and tables that only depend on those
+pub struct FilterRenderer {
+    resolution: (usize, usize),
+    scale: usize,
+
+    // brightness (out of 256) of every row and column inside a CHIP-8 pixel
+    scanline: Vec<u32>,
+    grid: Vec<u32>,
+
+    // glow around lit pixels, RGB at the source resolution
+    glow: Vec<u32>,
+    // the filtered frame before the barrel distortion
+    flat: Vec<u8>,
+    // for every row of the bent frame, the runs of pixels of `flat` it shows
+    barrel: Vec<Vec<Run>>,
+}
+
+impl FilterRenderer {
+    pub fn new(resolution: (u32, u32), scale: u32) -> Self {
+        let resolution = (resolution.0 as usize, resolution.1 as usize);
+        let scale = scale.max(1) as usize;
+        let (width, height) = (resolution.0 * scale, resolution.1 * scale);
+
+        // scanlines are brightest in their middle
+        let scanline = (0..scale)
+            .map(|row| {
+                let distance = (2 * row + 1) as f32 / scale as f32 - 1.0;
+                256 - ((256 - SCANLINE_EDGE) as f32 * distance * distance) as u32
+            })
+            .collect();
+
+        // grid lines are drawn on the last row and column of a pixel, if
+        // there is room for them
+        let grid = (0..scale)
+            .map(|idx| if scale >= 3 && idx == scale - 1 { GRID_LINE } else { 256 })
+            .collect();
+
+        FilterRenderer {
+            resolution,
+            scale,
+            scanline,
+            grid,
+            glow: vec![0; resolution.0 * resolution.1 * 3],
+            flat: vec![0; width * height * 3],
+            barrel: barrel_map(width, height),
+        }
+    }
+
+    // Whether this renderer is the one for `resolution` and `scale`
+    pub fn renders(&self, resolution: (u32, u32), scale: u32) -> bool {
+        self.resolution == (resolution.0 as usize, resolution.1 as usize) && self.scale == scale.max(1) as usize
+    }
+
+    // Size of the frames rendered
+    pub fn frame_size(&self) -> (u32, u32) {
+        ((self.resolution.0 * self.scale) as u32, (self.resolution.1 * self.scale) as u32)
+    }
+
+    // Scales up `source` (packed RGB24 at the resolution) with `filter` into
+    // the RGB24 `buffer` of `frame_size()`
+    pub fn render(&mut self, filter: Filter, source: &[u8], buffer: &mut [u8], pitch: usize) {
+        if filter.bloom {
+            self.update_glow(source);
+        }
+
+        if filter.barrel {
+            let mut flat = std::mem::take(&mut self.flat);
+            self.render_flat(filter, source, &mut flat, self.resolution.0 * self.scale * 3);
+            self.bend(&flat, buffer, pitch);
+            self.flat = flat;
+        }
+        else {
+            self.render_flat(filter, source, buffer, pitch);
+        }
+    }
+
+    fn render_flat(&mut self, filter: Filter, source: &[u8], buffer: &mut [u8], pitch: usize) {
+        let scale = self.scale;
+        let (source_width, source_height) = self.resolution;
+        let width = source_width * scale;
+
+        // the brightness of every row of a CHIP-8 pixel, and of the grid
+        // column on its right
+        let weights: Vec<u32> = (0..scale)
+            .map(|row| {
+                let mut weight = 256;
+                if filter.scanlines {
+                    weight = weight * self.scanline[row] / 256;
+                }
+                if filter.grid {
+                    weight = weight * self.grid[row] / 256;
+                }
+                weight
+            })
+            .collect();
+        let grid_column = if filter.grid { self.grid[scale - 1] } else { 256 };
+
+        let row_bytes = source_width * 3;
+        for source_y in 0..source_height {
+            // a row of CHIP-8 pixels like the one above it, the blank ones
+            // mostly, is a copy of it
+            let this = source_y * row_bytes..(source_y + 1) * row_bytes;
+            let above = this.start.saturating_sub(row_bytes)..this.start;
+            if source_y > 0
+                && source[this.clone()] == source[above.clone()]
+                && (!filter.bloom || self.glow[this] == self.glow[above])
+            {
+                let offset = source_y * scale * pitch;
+                buffer.copy_within(offset - scale * pitch..offset, offset);
+                continue;
+            }
+
+            for row in 0..scale {
+                let offset = (source_y * scale + row) * pitch;
+
+                // a row as bright as one above it is a copy of that one
+                if let Some(same) = (0..row).find(|above| weights[*above] == weights[row]) {
+                    let above = (source_y * scale + same) * pitch;
+                    buffer.copy_within(above..above + width * 3, offset);
+                    continue;
+                }
+
+                let target = &mut buffer[offset..offset + width * 3];
+                for (source_x, cell) in target.chunks_exact_mut(scale * 3).enumerate() {
+                    let pixel = (source_y * source_width + source_x) * 3;
+                    for channel in 0..3 {
+                        let value = source[pixel + channel] as u32;
+                        // only the light spreading out of lit pixels, so that
+                        // a bright background does not glow on its own
+                        let halo = if filter.bloom { self.glow[pixel + channel].saturating_sub(value) * BLOOM_STRENGTH } else { 0 };
+                        cell[channel] = shade(value * 256 + halo, weights[row]);
+                        cell[(scale - 1) * 3 + channel] = shade(value * grid_column + halo, weights[row]);
+                    }
+
+                    // the first column copied over the others but the grid
+                    // one, doubling the copy every time
+                    let mut filled = 1;
+                    while filled < scale - 1 {
+                        let count = filled.min(scale - 1 - filled);
+                        cell.copy_within(..count * 3, filled * 3);
+                        filled += count;
+                    }
+                }
+            }
+        }
+    }
+
+    // Blurs the source into `glow`, a separable [1 2 1] kernel applied twice
+    fn update_glow(&mut self, source: &[u8]) {
+        let mut blurred: Vec<u32> = source.iter().map(|&value| value as u32).collect();
+        let mut scratch = vec![0u32; blurred.len()];
+
+        for _ in 0..2 {
+            blur(&blurred, &mut scratch, 1, self.resolution.0);
+            blur(&scratch, &mut blurred, self.resolution.0, self.resolution.1);
+        }
+
+        self.glow = blurred;
+    }
+
+    fn bend(&self, flat: &[u8], buffer: &mut [u8], pitch: usize) {
+        let width = self.resolution.0 * self.scale;
+
+        for (y, runs) in self.barrel.iter().enumerate() {
+            let target = &mut buffer[y * pitch..y * pitch + width * 3];
+            for run in runs {
+                let out = &mut target[run.x * 3..(run.x + run.length) * 3];
+                match run.source {
+                    Some(pixel) => out.copy_from_slice(&flat[pixel * 3..(pixel + run.length) * 3]),
+                    None => out.fill(0),
+                }
+            }
+        }
+    }
+}
+
+fn shade(value: u32, weight: u32) -> u8 {
+    (value * weight / (256 * 256)).min(255) as u8
+}
+
+// Blurs RGB `source` along one axis: `step` is the distance in pixels
+// between neighbours along it and `length` the number of pixels on it
+fn blur(source: &[u32], target: &mut [u32], step: usize, length: usize) {
+    let pixels = source.len() / 3;
+
+    for pixel in 0..pixels {
+        let position = (pixel / step) % length;
+        let previous = if position > 0 { pixel - step } else { pixel };
+        let next = if position + 1 < length { pixel + step } else { pixel };
+
+        for channel in 0..3 {
+            target[pixel * 3 + channel] = (source[previous * 3 + channel]
+                + 2 * source[pixel * 3 + channel]
+                + source[next * 3 + channel])
+                / 4;
+        }
+    }
+}
+
+// Pixels of a row of the bent frame showing consecutive pixels of the flat
+// frame, from `source`, or black outside of the picture
+struct Run {
+    x: usize,
+    length: usize,
+    source: Option<usize>,
+}
+
+// For every row of a `width` x `height` frame, the runs of pixels of the
+// flat frame it shows once bent
+fn barrel_map(width: usize, height: usize) -> Vec<Vec<Run>> {
+    let mut map = Vec::with_capacity(height);
+
+    for y in 0..height {
+        let mut runs: Vec<Run> = Vec::new();
+        for x in 0..width {
+            // coordinates from -1 to 1, pushed outwards the further they are
+            // from the centre
+            let u = (2 * x + 1) as f32 / width as f32 - 1.0;
+            let v = (2 * y + 1) as f32 / height as f32 - 1.0;
+            let bend = 1.0 + BARREL_CURVATURE * (u * u + v * v);
+            let (u, v) = (u * bend, v * bend);
+
+            let inside = u.abs() < 1.0 && v.abs() < 1.0;
+            let source = inside.then(|| {
+                let x = ((u + 1.0) / 2.0 * width as f32) as usize;
+                let y = ((v + 1.0) / 2.0 * height as f32) as usize;
+                y * width + x
+            });
+
+            match runs.last_mut() {
+                Some(run) if run.source.map(|start| start + run.length) == source || (run.source.is_none() && source.is_none()) => {
+                    run.length += 1;
+                }
+                _ => runs.push(Run { x, length: 1, source }),
+            }
+        }
+        map.push(runs);
+    }
+
+    map
+}
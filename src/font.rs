@@ -0,0 +1,159 @@
+// Small bitmap font for the text drawn by the frontend
+//
+// Glyphs are 4x5 pixels stored like the CHIP-8 SPRITE_CHARS, one byte per
+// row with the pixels in the high nibble; the hex digits are those very
+// sprites. Lowercase letters are drawn as uppercase and anything missing as
+// '?'.
+
+use crate::chip8::SPRITE_CHARS;
+use crate::palette::Rgb;
+
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 5;
+
+// Distance between the start of two characters and of two lines
+pub const ADVANCE: usize = GLYPH_WIDTH + 1;
+pub const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+
+// The letters past F and the other characters, the hex digits are the
+// CHIP-8 sprites
+const GLYPHS: [(char, [u8; GLYPH_HEIGHT]); 53] = [
+    ('G', [0xF0, 0x80, 0xB0, 0x90, 0xF0]),
+    ('H', [0x90, 0x90, 0xF0, 0x90, 0x90]),
+    ('I', [0xE0, 0x40, 0x40, 0x40, 0xE0]),
+    ('J', [0x10, 0x10, 0x10, 0x90, 0xF0]),
+    ('K', [0x90, 0xA0, 0xC0, 0xA0, 0x90]),
+    ('L', [0x80, 0x80, 0x80, 0x80, 0xF0]),
+    ('M', [0x90, 0xF0, 0xF0, 0x90, 0x90]),
+    ('N', [0x90, 0xD0, 0xB0, 0x90, 0x90]),
+    ('O', [0xF0, 0x90, 0x90, 0x90, 0xF0]),
+    ('P', [0xF0, 0x90, 0xF0, 0x80, 0x80]),
+    ('Q', [0xF0, 0x90, 0x90, 0xB0, 0xF0]),
+    ('R', [0xE0, 0x90, 0xE0, 0xA0, 0x90]),
+    ('S', [0xF0, 0x80, 0xF0, 0x10, 0xF0]),
+    ('T', [0xF0, 0x40, 0x40, 0x40, 0x40]),
+    ('U', [0x90, 0x90, 0x90, 0x90, 0xF0]),
+    ('V', [0x90, 0x90, 0x90, 0xA0, 0x40]),
+    ('W', [0x90, 0x90, 0xF0, 0xF0, 0x90]),
+    ('X', [0x90, 0x90, 0x60, 0x90, 0x90]),
+    ('Y', [0xA0, 0xA0, 0x40, 0x40, 0x40]),
+    ('Z', [0xF0, 0x10, 0x60, 0x80, 0xF0]),
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x40]),
+    (',', [0x00, 0x00, 0x00, 0x40, 0x80]),
+    (':', [0x00, 0x40, 0x00, 0x40, 0x00]),
+    (';', [0x00, 0x40, 0x00, 0x40, 0x80]),
+    ('-', [0x00, 0x00, 0xF0, 0x00, 0x00]),
+    ('+', [0x00, 0x40, 0xE0, 0x40, 0x00]),
+    ('/', [0x10, 0x10, 0x20, 0x40, 0x80]),
+    ('(', [0x20, 0x40, 0x40, 0x40, 0x20]),
+    (')', [0x40, 0x20, 0x20, 0x20, 0x40]),
+    ('[', [0x60, 0x40, 0x40, 0x40, 0x60]),
+    (']', [0x60, 0x20, 0x20, 0x20, 0x60]),
+    ('!', [0x40, 0x40, 0x40, 0x00, 0x40]),
+    ('?', [0xE0, 0x10, 0x60, 0x00, 0x40]),
+    ('\'', [0x40, 0x40, 0x00, 0x00, 0x00]),
+    ('"', [0xA0, 0xA0, 0x00, 0x00, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0xF0]),
+    ('=', [0x00, 0xF0, 0x00, 0xF0, 0x00]),
+    ('<', [0x20, 0x40, 0x80, 0x40, 0x20]),
+    ('>', [0x80, 0x40, 0x20, 0x40, 0x80]),
+    ('%', [0x90, 0x10, 0x20, 0x40, 0x90]),
+    ('#', [0xA0, 0xF0, 0xA0, 0xF0, 0xA0]),
+    ('*', [0x00, 0xA0, 0x40, 0xA0, 0x00]),
+    ('&', [0x40, 0xA0, 0x40, 0xA0, 0x50]),
+    ('|', [0x40, 0x40, 0x40, 0x40, 0x40]),
+    ('^', [0x40, 0xA0, 0x00, 0x00, 0x00]),
+    ('~', [0x00, 0x50, 0xA0, 0x00, 0x00]),
+    ('$', [0x70, 0xA0, 0x60, 0x50, 0xE0]),
+    ('@', [0x60, 0x90, 0xB0, 0x80, 0x70]),
+    ('{', [0x30, 0x20, 0x40, 0x20, 0x30]),
+    ('}', [0xC0, 0x40, 0x20, 0x40, 0xC0]),
+    ('`', [0x40, 0x20, 0x00, 0x00, 0x00]),
+    ('\\', [0x80, 0x80, 0x40, 0x20, 0x10]),
+];
+
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    if let Some(digit) = c.to_digit(16) {
+        return SPRITE_CHARS[digit as usize];
+    }
+    GLYPHS
+        .iter()
+        .find(|(glyph, _)| *glyph == c)
+        .or_else(|| GLYPHS.iter().find(|(glyph, _)| *glyph == '?'))
+        .map(|(_, rows)| *rows)
+        .unwrap()
+}
+
+// Width in pixels of `text` drawn at scale 1
+pub fn text_width(text: &str) -> usize {
+    (text.chars().count() * ADVANCE).saturating_sub(1)
+}
+
+// Lit pixels of `text` drawn at scale 1 with its top left corner at 0, 0
+pub fn text_pixels(text: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    text.chars().enumerate().flat_map(|(idx, c)| {
+        let rows = glyph(c);
+        (0..GLYPH_HEIGHT).flat_map(move |row| {
+            (0..GLYPH_WIDTH)
+                .filter(move |column| rows[row] & (0x80 >> column) != 0)
+                .map(move |column| (idx * ADVANCE + column, row))
+        })
+    })
+}
+
+// An RGB24 picture to draw text into
+pub struct Canvas<'a> {
+    pub buffer: &'a mut [u8],
+    pub pitch: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Canvas<'_> {
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, colour: Rgb) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                let offset = row * self.pitch + column * 3;
+                self.buffer[offset..offset + 3].copy_from_slice(&colour);
+            }
+        }
+    }
+
+    // Draws `text` with its top left corner at `x`, `y`, every font pixel
+    // as a `scale` x `scale` block; whatever does not fit is cut off
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, scale: usize, colour: Rgb) {
+        for (column, row) in text_pixels(text) {
+            self.fill_rect(x + column * scale, y + row * scale, scale, scale, colour);
+        }
+    }
+}
+
+// Splits `text` in lines of at most `columns` characters, at spaces where
+// possible
+pub fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > columns {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+
+        // words longer than a line are cut
+        while line.chars().count() > columns {
+            let rest = line.split_off(line.char_indices().nth(columns).unwrap().0);
+            lines.push(std::mem::replace(&mut line, rest));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
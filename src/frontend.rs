@@ -0,0 +1,185 @@
+// The SDL window and input, kept open across games
+//
+// SDL can only be initialised once per process and its event pump taken
+// once, so everything that outlives a single game lives here: the context,
+// the subsystems, the main window, the event pump and the gamepads.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::render::Canvas;
+use sdl2::video::{FullscreenType, Window};
+use sdl2::{AudioSubsystem, EventPump, GameControllerSubsystem, Sdl, VideoSubsystem};
+
+use crate::chip8;
+use crate::launcher::{Launcher, LAUNCHER_HEIGHT, LAUNCHER_WIDTH};
+use crate::menu::MenuInput;
+use crate::{streaming_texture, DisplaySettings, Result};
+
+pub const WINDOW_TITLE: &str = "chip-8 emulator";
+
+pub struct Frontend {
+    pub sdl_context: Sdl,
+    pub video_subsystem: VideoSubsystem,
+    pub audio_subsystem: AudioSubsystem,
+    pub canvas: Canvas<Window>,
+    pub event_pump: EventPump,
+
+    game_controller_subsystem: GameControllerSubsystem,
+    // gamepads plugged in, opened to get their events
+    controllers: Vec<GameController>,
+}
+
+impl Frontend {
+    pub fn new(display: &DisplaySettings) -> Result<Self> {
+        let sdl_context = sdl2::init()?;
+        let video_subsystem = sdl_context.video()?;
+        let audio_subsystem = sdl_context.audio()?;
+        let game_controller_subsystem = sdl_context.game_controller()?;
+
+        let window = video_subsystem.window(WINDOW_TITLE,
+            chip8::SCREEN_WIDTH as u32 * display.scale,
+            chip8::SCREEN_HEIGTH as u32 * display.scale,
+            )
+            .position_centered()
+            .resizable()
+            .build()?;
+
+        let mut canvas = window.into_canvas().build()?;
+        if display.fullscreen {
+            canvas.window_mut().set_fullscreen(FullscreenType::Desktop)?;
+            sdl_context.mouse().show_cursor(false);
+        }
+        let [r, g, b] = display.palette.background();
+        canvas.set_draw_color(Color::RGB(r, g, b));
+        canvas.clear();
+        canvas.present();
+
+        let event_pump = sdl_context.event_pump()?;
+
+        Ok(Frontend {
+            sdl_context,
+            video_subsystem,
+            audio_subsystem,
+            canvas,
+            event_pump,
+            game_controller_subsystem,
+            controllers: Vec::new(),
+        })
+    }
+
+    // The events since the last call. Gamepads are opened as they are
+    // plugged in, which SDL also reports for those present at start.
+    pub fn poll_events(&mut self) -> Vec<Event> {
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+
+        for event in &events {
+            match event {
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Ok(controller) = self.game_controller_subsystem.open(*which) {
+                        self.controllers.push(controller);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers.retain(|controller| controller.instance_id() != *which);
+                }
+                _ => {}
+            }
+        }
+
+        events
+    }
+
+    // F11 switches between a window and fullscreen
+    pub fn toggle_fullscreen(&mut self) -> Result<()> {
+        let fullscreen = self.canvas.window().fullscreen_state() == FullscreenType::Off;
+        self.canvas.window_mut().set_fullscreen(if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off })?;
+        self.sdl_context.mouse().show_cursor(!fullscreen);
+        Ok(())
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<()> {
+        self.canvas.window_mut().set_title(title)?;
+        Ok(())
+    }
+
+    // Shows the launcher until a program is picked, or None to quit
+    pub fn choose_rom(&mut self, launcher: &mut Launcher, display: &DisplaySettings) -> Result<Option<PathBuf>> {
+        self.set_title(WINDOW_TITLE)?;
+
+        let texture_creator = self.canvas.texture_creator();
+        let resolution = (LAUNCHER_WIDTH as u32, LAUNCHER_HEIGHT as u32);
+        let mut texture = streaming_texture(&texture_creator, resolution)?;
+
+        loop {
+            for event in self.poll_events() {
+                match menu_input(&event) {
+                    Some(MenuInput::Back) => return Ok(None),
+                    Some(MenuInput::Select) => {
+                        if let Some(entry) = launcher.selected() {
+                            return Ok(Some(entry.path.clone()));
+                        }
+                    }
+                    Some(MenuInput::Up) => launcher.move_selection(-1),
+                    Some(MenuInput::Down) => launcher.move_selection(1),
+                    Some(MenuInput::Left) => launcher.page(false),
+                    Some(MenuInput::Right) => launcher.page(true),
+                    None => {}
+                }
+
+                match event {
+                    Event::Quit { .. } => return Ok(None),
+                    Event::KeyDown { keycode: Some(keycode), .. } => match keycode {
+                        Keycode::PageUp => launcher.page(false),
+                        Keycode::PageDown => launcher.page(true),
+                        Keycode::Home => launcher.select_first(),
+                        Keycode::End => launcher.select_last(),
+                        Keycode::F11 => self.toggle_fullscreen()?,
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+
+            let viewport = display.scaling.viewport(self.canvas.output_size()?, resolution);
+            texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                launcher.render(buffer, pitch, &display.palette);
+            })?;
+            self.canvas.clear();
+            self.canvas.copy(&texture, None, viewport)?;
+            self.canvas.present();
+
+            ::std::thread::sleep(Duration::from_millis(16));
+        }
+    }
+}
+
+// The menu input of `event`: arrow keys, Enter and Esc on the keyboard, the
+// d-pad, A and B on a gamepad
+pub fn menu_input(event: &Event) -> Option<MenuInput> {
+    match event {
+        Event::KeyDown { keycode: Some(keycode), .. } => match keycode {
+            Keycode::Up => Some(MenuInput::Up),
+            Keycode::Down => Some(MenuInput::Down),
+            Keycode::Left => Some(MenuInput::Left),
+            Keycode::Right => Some(MenuInput::Right),
+            Keycode::Return | Keycode::KpEnter | Keycode::Space => Some(MenuInput::Select),
+            Keycode::Escape => Some(MenuInput::Back),
+            _ => None,
+        },
+        Event::ControllerButtonDown { button, .. } => match button {
+            Button::DPadUp => Some(MenuInput::Up),
+            Button::DPadDown => Some(MenuInput::Down),
+            Button::DPadLeft => Some(MenuInput::Left),
+            Button::DPadRight => Some(MenuInput::Right),
+            Button::A => Some(MenuInput::Select),
+            Button::B => Some(MenuInput::Back),
+            _ => None,
+        },
+        _ => None,
+    }
+}
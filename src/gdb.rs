@@ -0,0 +1,466 @@
+// GDB remote serial protocol stub
+//
+// Lets an external gdb client attach to the emulator over local TCP
+// (`target remote localhost:<port>`). The stub is polled once per frame
+// from the main loop and drives the `Chip8` core instruction by instruction
+// while gdb wants it running, so breakpoints stop in the middle of a frame.
+
+use std::collections::HashSet;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use crate::chip8::{Chip8, PcOutOfMemoryBounds, MEMORY_SIZE, REGISTERS_COUNT};
+use crate::tracer::Tracer;
+
+// Register layout exposed to gdb, in `g` packet order:
+// V0-VF (8 bit), I (16 bit), PC (16 bit), DT (8 bit), ST (8 bit)
+const REG_I: usize = REGISTERS_COUNT;
+const REG_PC: usize = REGISTERS_COUNT + 1;
+const REG_DT: usize = REGISTERS_COUNT + 2;
+const REG_ST: usize = REGISTERS_COUNT + 3;
+const REGS_TOTAL: usize = REGISTERS_COUNT + 4;
+
+const SIGTRAP: u8 = 5;
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGSEGV: u8 = 11;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Halted,
+    Running,
+    Stepping,
+    Killed,
+}
+
+pub struct GdbStub {
+    listener: TcpListener,
+    stream: Option<TcpStream>,
+
+    // bytes received from the client that do not form a full packet yet
+    input: Vec<u8>,
+
+    breakpoints: HashSet<u16>,
+    state: RunState,
+}
+
+impl GdbStub {
+    // Binds the stub to the loopback interface. The machine starts halted
+    // and stays so until a client connects and resumes it.
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(GdbStub {
+            listener,
+            stream: None,
+            input: Vec::new(),
+            breakpoints: HashSet::new(),
+            state: RunState::Halted,
+        })
+    }
+
+    pub fn halted(&self) -> bool {
+        self.state == RunState::Halted
+    }
+
+    // The client asked to kill the program, the emulator should quit
+    pub fn killed(&self) -> bool {
+        self.state == RunState::Killed
+    }
+
+    // Runs (the rest of) one emulated frame under debugger control: services
+    // pending gdb packets, then executes instructions until the frame is over,
+    // a breakpoint is hit or a single step completes.
+    pub fn run_frame(&mut self, emulator: &mut Chip8, key: u16, tracer: &mut dyn Tracer) -> crate::Result<()> {
+        self.poll(emulator)?;
+
+        if self.halted() || self.killed() {
+            return Ok(());
+        }
+
+        if !emulator.frame_pending() {
+            emulator.begin_frame(key);
+            tracer.frame_started(emulator);
+        }
+
+        while emulator.frame_pending() {
+            // a failing instruction halts the target where it failed, for the
+            // client to look at, instead of ending the emulator
+            if let Err(err) = emulator.step_traced(tracer) {
+                self.stop(if err.is::<PcOutOfMemoryBounds>() { SIGSEGV } else { SIGILL })?;
+                break;
+            }
+
+            if self.state == RunState::Stepping || self.breakpoints.contains(&emulator.pc()) {
+                self.stop(SIGTRAP)?;
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn poll(&mut self, emulator: &mut Chip8) -> io::Result<()> {
+        if self.stream.is_none() {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nodelay(true)?;
+                    self.stream = Some(stream);
+                    self.input.clear();
+                    self.state = RunState::Halted;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if !self.receive()? {
+            // client went away, let the game carry on without it
+            self.stream = None;
+            self.breakpoints.clear();
+            self.state = RunState::Running;
+            return Ok(());
+        }
+
+        while let Some(packet) = self.next_packet()? {
+            self.handle_packet(&packet, emulator)?;
+        }
+
+        Ok(())
+    }
+
+    // Reads whatever the client has sent without blocking.
+    // Returns false if the connection was closed.
+    fn receive(&mut self) -> io::Result<bool> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(false);
+        };
+
+        stream.set_nonblocking(true)?;
+        let mut chunk = [0u8; 1024];
+        let result = loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => break Ok(false),
+                Ok(n) => self.input.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break Ok(true),
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => break Ok(false),
+                Err(e) => break Err(e),
+            }
+        };
+        stream.set_nonblocking(false)?;
+
+        result
+    }
+
+    // Extracts the next complete `$data#cs` packet from the input buffer,
+    // acknowledging it. A bare 0x03 byte is turned into an interrupt request.
+    fn next_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let Some(&first) = self.input.first() else {
+                return Ok(None);
+            };
+
+            match first {
+                b'$' => {
+                    let Some(hash) = self.input.iter().position(|&b| b == b'#') else {
+                        return Ok(None);
+                    };
+                    if self.input.len() < hash + 3 {
+                        return Ok(None);
+                    }
+
+                    let data = self.input[1..hash].to_vec();
+                    let checksum = std::str::from_utf8(&self.input[hash + 1..hash + 3])
+                        .ok()
+                        .and_then(|cs| u8::from_str_radix(cs, 16).ok());
+                    self.input.drain(..hash + 3);
+
+                    if checksum == Some(packet_checksum(&data)) {
+                        self.write_raw(b"+")?;
+                        return Ok(Some(data));
+                    }
+                    self.write_raw(b"-")?;
+                }
+                0x03 => {
+                    self.input.remove(0);
+                    if !self.halted() {
+                        self.stop(SIGINT)?;
+                    }
+                }
+                _ => {
+                    // acks from the client and line noise
+                    self.input.remove(0);
+                }
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &[u8], emulator: &mut Chip8) -> io::Result<()> {
+        let packet = String::from_utf8_lossy(packet).into_owned();
+        let (command, args) = packet.split_at_checked(1).unwrap_or(("", ""));
+
+        match command {
+            "?" => self.reply(&format!("S{:02x}", SIGTRAP)),
+            "g" => {
+                let regs: String = (0..REGS_TOTAL).map(|n| read_register(emulator, n)).collect();
+                self.reply(&regs)
+            }
+            "G" => {
+                let mut rest = args;
+                for n in 0..REGS_TOTAL {
+                    let width = register_width(n) * 2;
+                    if rest.len() < width {
+                        break;
+                    }
+                    let (value, tail) = rest.split_at(width);
+                    write_register(emulator, n, value);
+                    rest = tail;
+                }
+                self.reply("OK")
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGS_TOTAL => self.reply(&read_register(emulator, n)),
+                _ => self.reply("E01"),
+            },
+            "P" => {
+                let parsed = args
+                    .split_once('=')
+                    .and_then(|(n, value)| Some((usize::from_str_radix(n, 16).ok()?, value)));
+                match parsed {
+                    Some((n, value)) if n < REGS_TOTAL => {
+                        write_register(emulator, n, value);
+                        self.reply("OK")
+                    }
+                    _ => self.reply("E01"),
+                }
+            }
+            "m" => match parse_address_range(args) {
+                Some((addr, len)) if in_memory(addr, len) => {
+                    let hex: String = emulator.memory()[addr..addr + len]
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect();
+                    self.reply(&hex)
+                }
+                _ => self.reply("E01"),
+            },
+            "M" => {
+                let parsed = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_address_range(range)?, decode_hex(data)?)));
+                match parsed {
+                    Some(((addr, len), data)) if data.len() == len && in_memory(addr, len) => {
+                        emulator.memory_mut()[addr..addr + len].copy_from_slice(&data);
+                        self.reply("OK")
+                    }
+                    _ => self.reply("E01"),
+                }
+            }
+            "c" => {
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    emulator.set_pc(addr);
+                }
+                self.state = RunState::Running;
+                Ok(())
+            }
+            "s" => {
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    emulator.set_pc(addr);
+                }
+                self.state = RunState::Stepping;
+                Ok(())
+            }
+            "Z" | "z" => {
+                // only software (0) and hardware (1) breakpoints: type,addr,kind
+                let mut fields = args.split(',');
+                let kind = fields.next();
+                let addr = fields.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+                match (kind, addr) {
+                    (Some("0") | Some("1"), Some(addr)) => {
+                        if command == "Z" {
+                            self.breakpoints.insert(addr);
+                        } else {
+                            self.breakpoints.remove(&addr);
+                        }
+                        self.reply("OK")
+                    }
+                    _ => self.reply(""),
+                }
+            }
+            "H" => self.reply("OK"),
+            "T" => self.reply("OK"),
+            "k" => {
+                self.stream = None;
+                self.state = RunState::Killed;
+                Ok(())
+            }
+            "D" => {
+                self.reply("OK")?;
+                self.stream = None;
+                self.breakpoints.clear();
+                self.state = RunState::Running;
+                Ok(())
+            }
+            "q" => self.handle_query(args),
+            _ => self.reply(""),
+        }
+    }
+
+    fn handle_query(&mut self, query: &str) -> io::Result<()> {
+        if query.starts_with("Supported") {
+            return self.reply("PacketSize=1000;qXfer:features:read+;swbreak+;hwbreak+");
+        }
+        if query == "Attached" {
+            return self.reply("1");
+        }
+        if query == "C" {
+            return self.reply("QC1");
+        }
+        if query == "fThreadInfo" {
+            return self.reply("m1");
+        }
+        if query == "sThreadInfo" {
+            return self.reply("l");
+        }
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            return match parse_address_range(range) {
+                Some((offset, len)) => {
+                    let xml = TARGET_XML.as_bytes();
+                    let start = offset.min(xml.len());
+                    let end = start.saturating_add(len).min(xml.len());
+                    let marker = if end == xml.len() { "l" } else { "m" };
+                    let chunk = String::from_utf8_lossy(&xml[start..end]);
+                    self.reply(&format!("{}{}", marker, escape_binary(&chunk)))
+                }
+                None => self.reply("E01"),
+            };
+        }
+
+        self.reply("")
+    }
+
+    fn stop(&mut self, signal: u8) -> io::Result<()> {
+        self.state = RunState::Halted;
+        self.reply(&format!("S{:02x}", signal))
+    }
+
+    fn reply(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, packet_checksum(data.as_bytes()));
+        self.write_raw(packet.as_bytes())
+    }
+
+    fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.write_all(bytes),
+            None => Ok(()),
+        }
+    }
+}
+
+////// Packet helpers
+fn packet_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn parse_address_range(range: &str) -> Option<(usize, usize)> {
+    let (addr, len) = range.split_once(',')?;
+    Some((usize::from_str_radix(addr, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+// Whether the `len` bytes from `addr`, as sent by the client, are all in memory
+fn in_memory(addr: usize, len: usize) -> bool {
+    addr.checked_add(len).is_some_and(|end| end <= MEMORY_SIZE)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    // only ASCII can be sliced two bytes at a time
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+// '#', '$', '}' and '*' have to be escaped inside binary replies
+fn escape_binary(data: &str) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for c in data.chars() {
+        if matches!(c, '#' | '$' | '}' | '*') {
+            escaped.push('}');
+            escaped.push((c as u8 ^ 0x20) as char);
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+////// Register helpers
+fn register_width(n: usize) -> usize {
+    match n {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+// gdb reads registers in the byte order of its target, little-endian unless
+// told otherwise, so the 16 bit registers are sent low byte first although
+// CHIP-8 itself is big-endian
+fn read_register(emulator: &Chip8, n: usize) -> String {
+    let word = |value: u16| value.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    match n {
+        REG_I => word(emulator.i()),
+        REG_PC => word(emulator.pc()),
+        REG_DT => format!("{:02x}", emulator.delay_timer()),
+        REG_ST => format!("{:02x}", emulator.sound_timer()),
+        _ => format!("{:02x}", emulator.registers()[n]),
+    }
+}
+
+// Takes the value low byte first, as `read_register` sends it
+fn write_register(emulator: &mut Chip8, n: usize, value: &str) {
+    let value = match decode_hex(value).as_deref() {
+        Some(&[low]) => low as u16,
+        Some(&[low, high]) => u16::from_le_bytes([low, high]),
+        _ => return,
+    };
+
+    match n {
+        REG_I => emulator.set_i(value),
+        REG_PC => emulator.set_pc(value),
+        REG_DT => emulator.set_delay_timer(value as u8),
+        REG_ST => emulator.set_sound_timer(value as u8),
+        _ => emulator.registers_mut()[n] = value as u8,
+    }
+}
@@ -0,0 +1,113 @@
+// Memory access heatmap
+//
+// Records reads, writes and executes of every `memory` address and keeps a
+// fading intensity per address and access kind. Rendered as a 64x64 grid
+// (one cell per address): writes are red, reads green and executes blue,
+// so self-modifying code shows up magenta and sprite tables green. Addresses
+// touched at least once keep a dim glow after fading out.
+
+use std::ops::Range;
+
+use crate::chip8::{Chip8, MEMORY_SIZE};
+use crate::disasm::instruction_at;
+use crate::opcodes::Opcodes;
+use crate::tracer::Tracer;
+use crate::utilities::get_register_and_value;
+
+pub const HEATMAP_COLUMNS: usize = 64;
+pub const HEATMAP_ROWS: usize = MEMORY_SIZE.div_ceil(HEATMAP_COLUMNS);
+
+// intensity kept from one frame to the next
+const DECAY: f32 = 0.92;
+// intensity of addresses that were accessed at some point
+const AFTERGLOW: f32 = 0.12;
+
+const READ: usize = 0;
+const WRITE: usize = 1;
+const EXECUTE: usize = 2;
+
+pub struct Heatmap {
+    // [reads, writes, executes] intensity per address, 0.0 - 1.0
+    heat: Box<[[f32; 3]; MEMORY_SIZE]>,
+    touched: Box<[[bool; 3]; MEMORY_SIZE]>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap {
+            heat: Box::new([[0.0; 3]; MEMORY_SIZE]),
+            touched: Box::new([[false; 3]; MEMORY_SIZE]),
+        }
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn mark(&mut self, range: Range<usize>, kind: usize) {
+        for addr in range.start.min(MEMORY_SIZE)..range.end.min(MEMORY_SIZE) {
+            self.heat[addr][kind] = 1.0;
+            self.touched[addr][kind] = true;
+        }
+    }
+
+    // Fills an RGB24 buffer of HEATMAP_COLUMNS x HEATMAP_ROWS pixels
+    pub fn render(&self, buffer: &mut [u8], pitch: usize) {
+        for addr in 0..HEATMAP_ROWS * HEATMAP_COLUMNS {
+            let offset = (addr / HEATMAP_COLUMNS) * pitch + (addr % HEATMAP_COLUMNS) * 3;
+
+            let rgb = if addr < MEMORY_SIZE {
+                let channel = |kind: usize| {
+                    let floor = if self.touched[addr][kind] { AFTERGLOW } else { 0.0 };
+                    (self.heat[addr][kind].max(floor) * 255.0) as u8
+                };
+                [channel(WRITE), channel(READ), channel(EXECUTE)]
+            } else {
+                // past the end of memory
+                [40, 40, 40]
+            };
+
+            buffer[offset..offset + 3].copy_from_slice(&rgb);
+        }
+    }
+}
+
+impl Tracer for Heatmap {
+    fn frame_started(&mut self, _emulator: &Chip8) {
+        for heat in self.heat.iter_mut() {
+            for value in heat.iter_mut() {
+                *value *= DECAY;
+            }
+        }
+    }
+
+    fn before_step(&mut self, emulator: &Chip8) {
+        let pc = emulator.pc() as usize;
+        self.mark(pc..pc + 2, EXECUTE);
+
+        let Some(instruction_bytes) = instruction_at(emulator.memory(), pc) else {
+            return;
+        };
+        let (reads, writes) = memory_accesses(emulator, instruction_bytes);
+        self.mark(reads, READ);
+        self.mark(writes, WRITE);
+    }
+}
+
+// Memory read and written by an instruction (besides its own fetch), as
+// address ranges computed from the machine state right before it executes
+pub fn memory_accesses(emulator: &Chip8, instruction_bytes: u16) -> (Range<usize>, Range<usize>) {
+    let i = emulator.i() as usize;
+    let (x, value) = get_register_and_value(instruction_bytes).unwrap_or_default();
+    let none = 0..0;
+
+    match Opcodes::try_from(instruction_bytes) {
+        Ok(Opcodes::DrawSprite) => (i..i + (value & 0x0F) as usize, none),
+        Ok(Opcodes::StoreBCD) => (none, i..i + 3),
+        Ok(Opcodes::StoreRegsInMemoryFromRegI) => (none, i..i + x + 1),
+        Ok(Opcodes::LoadRegsInMemoryFromRegI) => (i..i + x + 1, none),
+        _ => (none.clone(), none),
+    }
+}
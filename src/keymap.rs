@@ -0,0 +1,87 @@
+// Keyboard keys pressing the keys of the CHIP-8 keypad
+//
+// The default layout puts the 4x4 COSMAC VIP keypad on the left of a QWERTY
+// keyboard:
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D  ->  Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+// Keyboard keys are named as SDL names them, e.g. "Q", "Up" or "Keypad 8".
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use sdl2::keyboard::Keycode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keymap {
+    // the keyboard key of every CHIP-8 key, if any
+    keys: [Option<Keycode>; 16],
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct KeymapError(String);
+impl std::error::Error for KeymapError { }
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid key binding: {}", self.0)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap {
+            keys: [
+                Some(Keycode::X),
+                Some(Keycode::Num1),
+                Some(Keycode::Num2),
+                Some(Keycode::Num3),
+                Some(Keycode::Q),
+                Some(Keycode::W),
+                Some(Keycode::E),
+                Some(Keycode::A),
+                Some(Keycode::S),
+                Some(Keycode::D),
+                Some(Keycode::Z),
+                Some(Keycode::C),
+                Some(Keycode::Num4),
+                Some(Keycode::R),
+                Some(Keycode::F),
+                Some(Keycode::V),
+            ],
+        }
+    }
+}
+
+impl Keymap {
+    // The CHIP-8 key pressed by `keycode`
+    pub fn key(&self, keycode: Keycode) -> Option<u8> {
+        self.keys.iter().position(|key| *key == Some(keycode)).map(|key| key as u8)
+    }
+
+    // Makes the keyboard key called `name` press the CHIP-8 `key`, a hex
+    // digit; it no longer presses the key it pressed before
+    pub fn bind(&mut self, key: &str, name: &str) -> Result<(), KeymapError> {
+        let key = u8::from_str_radix(key, 16)
+            .ok()
+            .filter(|_| key.len() == 1)
+            .ok_or_else(|| KeymapError(format!("'{}' is not a CHIP-8 key, expected 0 to F", key)))?;
+        let keycode = Keycode::from_name(name).ok_or_else(|| KeymapError(format!("'{}' is not a key name", name)))?;
+
+        for bound in self.keys.iter_mut().filter(|bound| **bound == Some(keycode)) {
+            *bound = None;
+        }
+        self.keys[key as usize] = Some(keycode);
+        Ok(())
+    }
+
+    // The name of the keyboard key of every bound CHIP-8 key, by hex digit
+    pub fn bindings(&self) -> BTreeMap<String, String> {
+        self.keys
+            .iter()
+            .enumerate()
+            .filter_map(|(key, keycode)| keycode.map(|keycode| (format!("{:X}", key), keycode.name())))
+            .collect()
+    }
+}
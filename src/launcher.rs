@@ -0,0 +1,188 @@
+// Menu listing the ROMs of a directory, shown when no program is given
+//
+// Every file of the directory is a candidate; known programs get their title
+// and description from the ROM database, the others are listed by file name.
+// The menu is drawn with the bitmap font into a small RGB picture which the
+// frontend scales up like the CHIP-8 screen.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::{LOADING_POINT, MEMORY_SIZE};
+use crate::font::{self, Canvas, ADVANCE, LINE_HEIGHT};
+use crate::palette::Palette;
+use crate::romdb::RomDatabase;
+
+pub const LAUNCHER_WIDTH: usize = 256;
+pub const LAUNCHER_HEIGHT: usize = 128;
+
+const MARGIN: usize = 4;
+const LIST_TOP: usize = 14;
+const LIST_ROWS: usize = 10;
+const DESCRIPTION_TOP: usize = LIST_TOP + LIST_ROWS * LINE_HEIGHT + 4;
+const DESCRIPTION_ROWS: usize = 3;
+const COLUMNS: usize = (LAUNCHER_WIDTH - 2 * MARGIN) / ADVANCE;
+
+#[derive(Debug, Clone)]
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub title: String,
+    pub description: String,
+}
+
+// The programs in `dir`, sorted by title. Hidden files, files that cannot be
+// read and files too large to be loaded are left out.
+pub fn list_roms(dir: &Path, db: &RomDatabase) -> crate::Result<Vec<RomEntry>> {
+    let mut entries = Vec::new();
+
+    for file in fs::read_dir(dir)? {
+        let path = file?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        if !path.is_file() || name.starts_with('.') {
+            continue;
+        }
+
+        let Ok(program) = fs::read(&path) else {
+            continue;
+        };
+        if program.is_empty() || program.len() > MEMORY_SIZE - LOADING_POINT {
+            continue;
+        }
+
+        let entry = match db.lookup(&program) {
+            Some(info) => RomEntry {
+                path,
+                title: info.title.clone(),
+                description: info.description.clone(),
+            },
+            None => RomEntry {
+                title: name.clone(),
+                description: format!("{} is not in the ROM database.", name),
+                path,
+            },
+        };
+        entries.push(entry);
+    }
+
+    entries.sort_by_key(|entry| entry.title.to_lowercase());
+    Ok(entries)
+}
+
+pub struct Launcher {
+    dir: PathBuf,
+    entries: Vec<RomEntry>,
+    selected: usize,
+    // first entry shown in the list
+    scroll: usize,
+    // why the last program chosen could not be played, shown until the
+    // selection moves
+    error: Option<String>,
+}
+
+impl Launcher {
+    pub fn new(dir: PathBuf, entries: Vec<RomEntry>) -> Self {
+        Launcher {
+            dir,
+            entries,
+            selected: 0,
+            scroll: 0,
+            error: None,
+        }
+    }
+
+    pub fn selected(&self) -> Option<&RomEntry> {
+        self.entries.get(self.selected)
+    }
+
+    // Shows `message` in place of the description of the selected program
+    pub fn show_error(&mut self, message: String) {
+        self.error = Some(message);
+    }
+
+    // Moves the selection by `offset` entries, stopping at both ends
+    pub fn move_selection(&mut self, offset: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        self.error = None;
+        let last = self.entries.len() - 1;
+        self.selected = self.selected.saturating_add_signed(offset).min(last);
+
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        }
+        if self.selected >= self.scroll + LIST_ROWS {
+            self.scroll = self.selected + 1 - LIST_ROWS;
+        }
+    }
+
+    pub fn select_first(&mut self) {
+        self.move_selection(isize::MIN);
+    }
+
+    pub fn select_last(&mut self) {
+        self.move_selection(isize::MAX);
+    }
+
+    // Moves by a page of the list
+    pub fn page(&mut self, forward: bool) {
+        let rows = LIST_ROWS as isize;
+        self.move_selection(if forward { rows } else { -rows });
+    }
+
+    // Draws the menu into the RGB24 `buffer` of LAUNCHER_WIDTH x LAUNCHER_HEIGHT
+    pub fn render(&self, buffer: &mut [u8], pitch: usize, palette: &Palette) {
+        let (background, foreground) = (palette.background(), palette.foreground());
+        let mut canvas = Canvas {
+            buffer,
+            pitch,
+            width: LAUNCHER_WIDTH,
+            height: LAUNCHER_HEIGHT,
+        };
+        canvas.fill_rect(0, 0, LAUNCHER_WIDTH, LAUNCHER_HEIGHT, background);
+
+        // header: the directory and how many programs it holds
+        let count = format!("{} ROMS", self.entries.len());
+        canvas.draw_text(MARGIN, MARGIN, &format!("CHIP-8  {}", self.dir.display()), 1, foreground);
+        canvas.draw_text(LAUNCHER_WIDTH - MARGIN - font::text_width(&count), MARGIN, &count, 1, foreground);
+        canvas.fill_rect(MARGIN, LIST_TOP - 4, LAUNCHER_WIDTH - 2 * MARGIN, 1, foreground);
+
+        if self.entries.is_empty() {
+            canvas.draw_text(MARGIN, LIST_TOP, "No ROMs found in this directory.", 1, foreground);
+        }
+
+        // the list, with the selected entry inverted
+        let visible = self.entries.iter().enumerate().skip(self.scroll).take(LIST_ROWS);
+        for (row, (idx, entry)) in visible.enumerate() {
+            let y = LIST_TOP + row * LINE_HEIGHT;
+            let mut colour = foreground;
+            if idx == self.selected {
+                canvas.fill_rect(MARGIN - 2, y - 1, LAUNCHER_WIDTH - 2 * MARGIN + 4, LINE_HEIGHT, foreground);
+                colour = background;
+            }
+            let title: String = entry.title.chars().take(COLUMNS).collect();
+            canvas.draw_text(MARGIN, y, &title, 1, colour);
+        }
+
+        // scroll marks when entries are out of sight
+        if self.scroll > 0 {
+            canvas.draw_text(LAUNCHER_WIDTH - MARGIN - font::GLYPH_WIDTH, LIST_TOP, "^", 1, foreground);
+        }
+        if self.scroll + LIST_ROWS < self.entries.len() {
+            let y = LIST_TOP + (LIST_ROWS - 1) * LINE_HEIGHT;
+            canvas.draw_text(LAUNCHER_WIDTH - MARGIN - font::GLYPH_WIDTH, y, "v", 1, foreground);
+        }
+
+        canvas.fill_rect(MARGIN, DESCRIPTION_TOP - 3, LAUNCHER_WIDTH - 2 * MARGIN, 1, foreground);
+        let description = self.error.as_ref().or(self.selected().map(|entry| &entry.description));
+        if let Some(description) = description {
+            let lines = font::wrap(description, COLUMNS);
+            for (row, line) in lines.iter().take(DESCRIPTION_ROWS).enumerate() {
+                canvas.draw_text(MARGIN, DESCRIPTION_TOP + row * LINE_HEIGHT, line, 1, foreground);
+            }
+        }
+
+        let help = "UP/DOWN: MOVE  ENTER: PLAY  ESC: QUIT";
+        canvas.draw_text(MARGIN, LAUNCHER_HEIGHT - MARGIN - font::GLYPH_HEIGHT, help, 1, foreground);
+    }
+}
@@ -1,198 +1,1311 @@
 extern crate sdl2;
 
+mod analyser;
+mod asm;
+mod audio;
+mod cfg;
+mod cheats;
 mod chip8;
+mod config;
+mod coverage;
+mod disasm;
+mod filters;
+mod font;
+mod frontend;
+mod gdb;
+mod heatmap;
+mod keymap;
+mod launcher;
+mod menu;
 mod opcodes;
+mod osd;
+mod palette;
+mod platform;
+mod profiler;
+mod quirks;
+mod recording;
+mod render;
+mod romdb;
+mod screenshot;
+mod script;
+mod sprites;
 mod stack;
+mod suite;
+mod tracer;
+mod tui;
 mod utilities;
+mod watch;
 
-use sdl2::{event::Event, pixels::PixelFormatEnum};
-use sdl2::keyboard::Keycode;
+use sdl2::{event::{Event, WindowEvent}, pixels::PixelFormatEnum};
+use sdl2::controller::Button;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::Color;
+use sdl2::render::{Texture, TextureCreator};
+use sdl2::video::WindowContext;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 
-use std::time::{Duration, Instant};
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
 
-use utilities::{SquareWave, DESIRED_AUDIO_SPEC};
+use utilities::DESIRED_AUDIO_SPEC;
+use audio::{RingPlayer, SampleRing, SoundRenderer, Waveform};
+use cheats::Cheats;
 use chip8::Chip8;
+use config::{AudioLayer, Config, ConfigLayer, ConfigLayers, DisplayLayer, EmulationLayer, FrontendKind};
+use coverage::Coverage;
+use filters::{Filter, FilterRenderer};
+use frontend::Frontend;
+use gdb::GdbStub;
+use heatmap::{Heatmap, HEATMAP_COLUMNS, HEATMAP_ROWS};
+use launcher::Launcher;
+use menu::{MenuAction, PauseMenu};
+use osd::Osd;
+use palette::Palette;
+use profiler::Profiler;
+use quirks::QuirkPreset;
+use recording::{GifRecorder, RawRecorder, Recording, WavRecorder};
+use render::{Persistence, Phosphor, Scaling};
+use script::Script;
+use romdb::{RomDatabase, RomInfo};
+use sprites::{SpriteSheet, SpriteViewer, PAGE_HEIGHT, SHEET_WIDTH};
+use tracer::Tracer;
+use tui::TuiDebugger;
+use watch::FileWatcher;
 
 pub const SCALE : u32 = 16;
+pub const HEATMAP_SCALE : u32 = 8;
+pub const SPRITES_SCALE : u32 = 4;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // without a command the arguments are those of `run`
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Play a program in a window, or pick one from the ROM menu (the default command)
+    Run(RunArgs),
+    /// Run a program for a number of frames without a window and print the machine state
+    Headless(HeadlessArgs),
+    /// Print the disassembly of a program, in the syntax `asm` reads
+    Disasm(DisasmArgs),
+    /// Assemble a source file into a program
+    Asm(AsmArgs),
+    /// Print the control-flow graph of a program, as Graphviz DOT or JSON
+    Cfg(CfgArgs),
+    /// Save memory as a PNG of 8 pixel wide sprites, to find the graphics of a program
+    Sprites(SpritesArgs),
+    /// Show the size, hash, platform, ROM database entry and static analysis of a program
+    Info(InfoArgs),
+    /// Run a suite of ROMs and check their screens against the expected ones
+    Test(TestArgs),
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Program to run, without it a menu lists the ROMs of `--roms`
+    program_file: Option<PathBuf>,
+
+    /// Directory the ROM menu lists
+    #[arg(long, value_name = "DIR", default_value = "programs")]
+    roms: PathBuf,
+
+    /// Where the game is shown: window, or tui for the terminal debugger next to it
+    #[arg(long, value_name = "FRONTEND")]
+    frontend: Option<FrontendKind>,
+
+    /// Show the terminal debugger (disassembly, memory, registers) next to the game, as `--frontend tui`
+    #[arg(long, conflicts_with_all = ["gdb", "frontend"])]
+    tui: bool,
+
+    /// Wait for a gdb client on this local TCP port (`target remote :PORT`)
+    #[arg(long, value_name = "PORT")]
+    gdb: Option<u16>,
+
+    /// Show memory reads, writes and executes as a heatmap in a second window
+    #[arg(long)]
+    heatmap: bool,
+
+    /// Show memory as 8 pixel wide sprites in a second window, with the font and the sprite I points to highlighted
+    #[arg(long)]
+    sprites: bool,
+
+    /// Turn on a cheat saved for the program, can be repeated
+    #[arg(long = "cheat", value_name = "NAME")]
+    cheats: Vec<String>,
+
+    /// Run a Rhai script at the start, with hooks on addresses and frames while the game runs
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["gdb", "tui"])]
+    script: Option<PathBuf>,
+
+    /// Restart the machine whenever the program file changes on disk
+    #[arg(long)]
+    watch: bool,
+
+    /// Keep the held keys, speed and quirks chosen in the menu when `--watch` restarts the machine
+    #[arg(long, requires = "watch")]
+    watch_keep: bool,
+
+    /// Show the settings resolved from the defaults, configuration file, ROM database and command line, then exit
+    #[arg(long)]
+    print_config: bool,
+
+    #[command(flatten)]
+    settings: SettingsArgs,
+
+    #[command(flatten)]
+    analysis: AnalysisArgs,
+
+    #[command(flatten)]
+    capture: CaptureArgs,
+}
+
+#[derive(Args, Debug)]
+struct HeadlessArgs {
+    /// Program to run
+    program_file: PathBuf,
+
+    /// Number of frames to run
+    #[arg(short = 'n', long, value_name = "FRAMES", default_value_t = 60)]
+    frames: u64,
+
+    /// Turn on a cheat saved for the program, can be repeated
+    #[arg(long = "cheat", value_name = "NAME")]
+    cheats: Vec<String>,
+
+    /// Run a Rhai script at the start, then its hooks during the frames
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
+
+    #[command(flatten)]
+    settings: SettingsArgs,
+
+    #[command(flatten)]
+    analysis: AnalysisArgs,
+
+    #[command(flatten)]
+    capture: CaptureArgs,
+}
+
+#[derive(Args, Debug)]
+struct DisasmArgs {
+    /// Program to disassemble
+    program_file: PathBuf,
+
+    /// Interpreter the Bnnn jumps are written for, by default the ROM database's or a guess from the opcodes
+    #[arg(long, value_name = "PRESET")]
+    quirks: Option<QuirkPreset>,
+}
+
+#[derive(Args, Debug)]
+struct AsmArgs {
+    /// Source to assemble
+    source_file: PathBuf,
+
+    /// Program to write, by default the source file with a .ch8 extension
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+struct CfgArgs {
+    /// Program to graph
+    program_file: PathBuf,
+
+    /// Print JSON rather than DOT
+    #[arg(long)]
+    json: bool,
+
+    /// JSON summary of a `--coverage` run, the Bnnn targets it saw resolve the computed jumps
+    #[arg(long, value_name = "FILE")]
+    coverage: Option<PathBuf>,
+
+    /// Interpreter the Bnnn jumps are written for, by default the ROM database's or a guess from the opcodes
+    #[arg(long, value_name = "PRESET")]
+    quirks: Option<QuirkPreset>,
+}
+
+#[derive(Args, Debug)]
+struct SpritesArgs {
+    /// Program to load
     program_file: PathBuf,
 
-    #[arg(short)]
-    scale: Option<u8>,
+    /// First address to show, in hex
+    #[arg(long, value_name = "ADDR", default_value = "000", value_parser = parse_address)]
+    from: usize,
+
+    /// Last address to show, in hex, by default the end of the program
+    #[arg(long, value_name = "ADDR", value_parser = parse_address)]
+    to: Option<usize>,
+
+    /// Bytes (lines) per sprite
+    #[arg(long, value_name = "LINES", default_value_t = 8, value_parser = clap::value_parser!(u8).range(1..=16))]
+    height: u8,
+
+    /// Number of frames to run first, for the memory and I of a running game
+    #[arg(short = 'n', long, value_name = "FRAMES", default_value_t = 0)]
+    frames: u64,
+
+    /// Size of a pixel in the image
+    #[arg(long, value_name = "SCALE", default_value_t = 4)]
+    image_scale: u32,
+
+    /// Image to write, by default <program>-sprites.png
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    settings: SettingsArgs,
+}
+
+#[derive(Args, Debug)]
+struct InfoArgs {
+    /// Program to describe
+    program_file: PathBuf,
+
+    /// Print the whole report as JSON: instructions used, SUPER-CHIP/XO-CHIP opcodes, self-modifying stores, 0nnn calls, unreachable bytes and sprites
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args, Debug)]
+struct TestArgs {
+    /// Suite file listing the ROMs and their expected screens
+    suite: PathBuf,
+
+    /// Only run the tests whose name contains this text
+    filter: Option<String>,
+}
+
+// The settings of the configuration file that can be given on the command line
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Settings")]
+struct SettingsArgs {
+    /// Size of a CHIP-8 pixel in the window when it opens
+    #[arg(short, value_parser = clap::value_parser!(u32).range(1..=64))]
+    scale: Option<u32>,
+
+    /// How the picture fills the window: integer (same sized pixels) or fit (as large as the aspect ratio allows)
+    #[arg(long, value_name = "SCALING")]
+    scaling: Option<Scaling>,
+
+    /// Start in fullscreen (F11 toggles it), `--fullscreen=false` overrides the configuration file
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    fullscreen: Option<bool>,
+
+    /// Colour theme (mono, amber, green, lcd, octo) or RRGGBB colours "bg,fg" / "bg,fg,plane2,both"
+    #[arg(long, value_name = "PALETTE")]
+    palette: Option<Palette>,
+
+    /// Anti-flicker mode: off, fade:STEP (0-255), decay:KEEP (0.0-1.0), blend:FRAMES or settle:FRAMES
+    #[arg(long, value_name = "MODE")]
+    persistence: Option<Persistence>,
+
+    /// CRT filters: none, scanlines, grid, bloom, barrel or crt, comma separated (F9 cycles them)
+    #[arg(long, value_name = "FILTERS")]
+    filter: Option<Filter>,
+
+    /// Show FPS, instructions per second, speed and the keypad over the game (F3 toggles it), `--osd=false` overrides the configuration file
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    osd: Option<bool>,
+
+    /// Interpreter behaviours the program expects: classic, chip8, schip or xochip
+    #[arg(long, value_name = "PRESET")]
+    quirks: Option<QuirkPreset>,
+
+    /// Emulation speed, as a multiple of the real CHIP-8 speed (0.1 to 16)
+    #[arg(long, value_name = "SPEED", value_parser = parse_speed)]
+    speed: Option<f64>,
+
+    /// Make a keyboard key press a CHIP-8 key, e.g. `--key 5=Up` (SDL key names)
+    #[arg(long = "key", value_name = "KEY=NAME", value_parser = parse_binding)]
+    keys: Vec<(String, String)>,
+
+    /// Pitch of the buzzer in Hz (20 to 20000)
+    #[arg(long, value_name = "HZ", value_parser = parse_tone)]
+    tone: Option<f32>,
+
+    /// Waveform of the buzzer: square, triangle, sine or noise
+    #[arg(long, value_name = "WAVEFORM")]
+    waveform: Option<Waveform>,
+
+    /// Volume of the buzzer, from 0.0 to 1.0
+    #[arg(long, value_name = "VOLUME", value_parser = parse_volume)]
+    volume: Option<f32>,
+
+    /// Start with the buzzer muted (F6 toggles it), `--mute=false` overrides the configuration file
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    mute: Option<bool>,
+
+    /// Configuration file to read instead of the user one (chip-8/config.toml in the XDG config directory)
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+}
+
+// Reports of the tools observing the execution
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Analysis")]
+struct AnalysisArgs {
+    /// Count executions and cost per address and opcode, write the report to FILE on exit
+    #[arg(long, value_name = "FILE")]
+    profile: Option<PathBuf>,
+
+    /// Track executed addresses and skip outcomes, write NAME.lst and NAME.json on exit
+    #[arg(long, value_name = "NAME")]
+    coverage: Option<PathBuf>,
+}
+
+// Pictures and recordings of the game
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Capture")]
+struct CaptureArgs {
+    /// Save the screen as a PNG at `--screenshot-frame` (headless: after the last frame)
+    #[arg(long, value_name = "FILE")]
+    screenshot: Option<PathBuf>,
+
+    /// Frame number (counting from 1) to take the `--screenshot` at
+    #[arg(long, value_name = "FRAME", requires = "screenshot")]
+    screenshot_frame: Option<u64>,
+
+    /// Size of a CHIP-8 pixel in the `--screenshot`, 1 for the raw 64x32 screen
+    #[arg(long, value_name = "SCALE", default_value_t = 1, requires = "screenshot")]
+    screenshot_scale: u32,
+
+    /// Record the game to an animated GIF from the start (F10 starts/stops recording anyway)
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+
+    /// Record raw RGB24 frames to NAME.rgb and 16 bit PCM audio to NAME.pcm for external encoders
+    #[arg(long, value_name = "NAME")]
+    record_raw: Option<PathBuf>,
+
+    /// Save the sound to a 16 bit mono WAV file, in emulated time (also with `headless`)
+    #[arg(long, value_name = "FILE")]
+    audio_out: Option<PathBuf>,
+
+    /// Size of a CHIP-8 pixel in the recordings
+    #[arg(long, value_name = "SCALE", default_value_t = 4, value_parser = parse_record_scale)]
+    record_scale: u32,
+}
+
+// How the screen is presented
+pub struct DisplaySettings {
+    pub scale: u32,
+    pub scaling: Scaling,
+    pub fullscreen: bool,
+    pub palette: Palette,
+    pub persistence: Persistence,
+    pub filter: Filter,
+    pub osd: bool,
+}
+
+// How the machine is emulated
+#[derive(Debug, Clone, Copy)]
+struct EmulationSettings {
+    quirks: QuirkPreset,
+    speed: f64,
+}
+
+// The program being played
+struct Game<'a> {
+    program: PathBuf,
+    emulator: Chip8,
+    settings: EmulationSettings,
+    database: &'a RomDatabase,
+    watcher: Option<FileWatcher>,
+    watch_keep: bool,
+}
+
+// How a game ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    Quit,
+    // back to the ROM menu
+    Menu,
+}
+
+// Optional debugging and analysis tools attached to the emulator
+#[derive(Default)]
+struct Tools {
+    gdb: Option<GdbStub>,
+    tui: Option<TuiDebugger>,
+    sprites: Option<SpriteViewer>,
+    cheats: Cheats,
+    script: Option<Script>,
+    analysis: Analysis,
+}
+
+// The tools observing every executed instruction
+#[derive(Default)]
+struct Analysis {
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    heatmap: Option<Heatmap>,
+}
+
+impl Analysis {
+    fn new(args: &AnalysisArgs) -> Self {
+        Analysis {
+            profiler: args.profile.clone().map(Profiler::new),
+            coverage: args.coverage.clone().map(Coverage::new),
+            heatmap: None,
+        }
+    }
+
+    // Writes the reports of the tools, `emulator` being the machine at the
+    // end
+    fn write_reports(&self, emulator: &Chip8) -> Result<()> {
+        if let Some(profiler) = &self.profiler {
+            profiler.write_report()?;
+        }
+        if let Some(coverage) = &self.coverage {
+            coverage.write_report(emulator)?;
+        }
+        Ok(())
+    }
+
+    fn tracers(&mut self) -> Vec<&mut dyn Tracer> {
+        let mut tracers: Vec<&mut dyn Tracer> = Vec::new();
+        if let Some(profiler) = self.profiler.as_mut() {
+            tracers.push(profiler);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            tracers.push(coverage);
+        }
+        if let Some(heatmap) = self.heatmap.as_mut() {
+            tracers.push(heatmap);
+        }
+        tracers
+    }
+}
+
+// Pictures and recordings taken of the running game
+struct Capture {
+    screenshot: Option<ScheduledScreenshot>,
+    recording: Recording,
+
+    // where F10 records the GIF to, by default a new file every time
+    gif_path: Option<PathBuf>,
+    record_scale: u32,
+}
+
+// Screenshot to take at a given frame
+struct ScheduledScreenshot {
+    path: PathBuf,
+    frame: Option<u64>,
+    scale: u32,
+}
+
+impl Capture {
+    // Starts the recordings asked for in `args`
+    fn new(args: &CaptureArgs, palette: &Palette) -> Result<Self> {
+        let mut capture = Capture {
+            screenshot: args.screenshot.clone().map(|path| ScheduledScreenshot {
+                path,
+                frame: args.screenshot_frame,
+                scale: args.screenshot_scale,
+            }),
+            recording: Recording::default(),
+            gif_path: args.record.clone(),
+            record_scale: args.record_scale,
+        };
+        if let Some(path) = &args.record {
+            capture.recording.gif = Some(GifRecorder::create(path, args.record_scale, palette)?);
+        }
+        if let Some(name) = &args.record_raw {
+            let raw = RawRecorder::create(name, args.record_scale, palette)?;
+            let (width, height) = raw.frame_size();
+            println!("Recording raw {}x{} RGB24 video at 60 fps and s16le mono audio at {} Hz", width, height, recording::RAW_AUDIO_FREQ);
+            capture.recording.raw = Some(raw);
+        }
+        if let Some(path) = &args.audio_out {
+            capture.recording.wav = Some(WavRecorder::create(path)?);
+        }
+        Ok(capture)
+    }
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
+    match cli.command.unwrap_or(Command::Run(cli.run)) {
+        Command::Run(args) => run_command(&args),
+        Command::Headless(args) => headless_command(&args),
+        Command::Disasm(args) => disasm_command(&args),
+        Command::Asm(args) => asm_command(&args),
+        Command::Cfg(args) => cfg_command(&args),
+        Command::Sprites(args) => sprites_command(&args),
+        Command::Info(args) => info_command(&args),
+        Command::Test(args) => suite::run_suite(&args.suite, args.filter.as_deref()),
+    }
+}
+
+fn run_command(args: &RunArgs) -> Result<()> {
+    let database = RomDatabase::builtin();
+    let frontend_kind = args.frontend.or(args.tui.then_some(FrontendKind::Tui));
+    let layers = ConfigLayers::load(args.settings.config.as_deref(), command_line_layer(&args.settings, frontend_kind))?;
+
+    if args.print_config {
+        let rom = args.program_file.as_deref().and_then(|program| rom_info(program, &database));
+        print!("{}", layers.print(rom)?);
+        return Ok(());
+    }
+
+    match &args.program_file {
+        Some(program) => {
+            let config = layers.resolve(rom_info(program, &database))?;
+            let mut frontend = Frontend::new(&config.display)?;
+            play(args, program, &database, &mut frontend, false, &config)?;
+        }
+        None => {
+            let entries = launcher::list_roms(&args.roms, &database)
+                .map_err(|err| format!("Cannot list the ROMs in {}: {}", args.roms.display(), err))?;
+            let mut launcher = Launcher::new(args.roms.clone(), entries);
+            // the menu is shown with the settings of no program in particular
+            let display = layers.resolve(None)?.display;
+            let mut frontend = Frontend::new(&display)?;
+
+            while let Some(program) = frontend.choose_rom(&mut launcher, &display)? {
+                let config = layers.resolve(rom_info(&program, &database))?;
+                // a program that fails goes back to the menu rather than out
+                match play(args, &program, &database, &mut frontend, true, &config) {
+                    Ok(Exit::Quit) => break,
+                    Ok(Exit::Menu) => {}
+                    Err(err) => {
+                        eprintln!("{}: {}", program.display(), err);
+                        let name = program.file_name().unwrap_or_default().to_string_lossy();
+                        launcher.show_error(format!("Cannot play {}: {}", name, err));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Runs the program for a number of frames and prints the state of the
+// machine at the end
+fn headless_command(args: &HeadlessArgs) -> Result<()> {
+    let program = &args.program_file;
+    let database = RomDatabase::builtin();
+    let layers = ConfigLayers::load(args.settings.config.as_deref(), command_line_layer(&args.settings, None))?;
+    let config = layers.resolve(rom_info(program, &database))?;
+
+    let mut emulator = power_on(program, &config.emulation)?;
+    let cheats = load_cheats(&args.cheats, &mut emulator)?;
+    let mut script = args.script.as_deref().map(|path| Script::load(path, &mut emulator, config.display.palette)).transpose()?;
+    let mut analysis = Analysis::new(&args.analysis);
+    let mut capture = Capture::new(&args.capture, &config.display.palette)?;
+
+    let result = run_headless(&mut emulator, args.frames, &config, &cheats, &mut script, &mut analysis, &mut capture);
+    capture.recording.finish()?;
+    analysis.write_reports(&emulator)?;
+    result?;
+
+    let registers = emulator.registers();
+    let hex = |values: &[u8]| values.iter().map(|value| format!("{:02X}", value)).collect::<Vec<String>>().join(" ");
+    let stack: Vec<String> = emulator.stack().as_slice().iter().map(|addr| format!("{:03X}", addr)).collect();
+
+    println!("frames        {}", script.as_ref().map_or(args.frames, Script::frames));
+    println!("instructions  {}", emulator.instructions());
+    println!("pc            {:03X}", emulator.pc());
+    println!("i             {:03X}", emulator.i());
+    println!("v0-v7         {}", hex(&registers[..8]));
+    println!("v8-vf         {}", hex(&registers[8..]));
+    println!("stack         {}", stack.join(" "));
+    println!("dt st         {:02X} {:02X}", emulator.delay_timer(), emulator.sound_timer());
+    println!("screen        {}", screenshot::hash(&emulator.gfx()));
+    print!("{}", screenshot::text(&emulator.gfx()));
+
+    Ok(())
+}
+
+fn disasm_command(args: &DisasmArgs) -> Result<()> {
+    let program = std::fs::read(&args.program_file)
+        .map_err(|err| format!("Cannot read {}: {}", args.program_file.display(), err))?;
+    let quirks = args.quirks.unwrap_or_else(|| program_quirks(&program));
+    print!("{}", disasm::listing(&program, &quirks.quirks()));
+    Ok(())
+}
+
+fn asm_command(args: &AsmArgs) -> Result<()> {
+    let source = std::fs::read_to_string(&args.source_file)
+        .map_err(|err| format!("Cannot read {}: {}", args.source_file.display(), err))?;
+    let program = asm::assemble(&source).map_err(|err| format!("{}: {}", args.source_file.display(), err))?;
+
+    let output = args.output.clone().unwrap_or_else(|| args.source_file.with_extension("ch8"));
+    std::fs::write(&output, &program)?;
+    println!("Assembled {} bytes to {}", program.len(), output.display());
+    Ok(())
+}
+
+fn cfg_command(args: &CfgArgs) -> Result<()> {
+    let program = std::fs::read(&args.program_file)
+        .map_err(|err| format!("Cannot read {}: {}", args.program_file.display(), err))?;
+    let indirect = match &args.coverage {
+        Some(path) => coverage::read_indirect_jumps(path)?,
+        None => BTreeMap::new(),
+    };
+
+    let quirks = args.quirks.unwrap_or_else(|| program_quirks(&program));
+    let graph = cfg::build(&program, &indirect, &quirks.quirks());
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&graph)?);
+    } else {
+        print!("{}", graph.dot());
+    }
+    Ok(())
+}
+
+// The quirks `program` was written for, the ROM database knows better than a
+// guess from the opcodes
+fn program_quirks(program: &[u8]) -> QuirkPreset {
+    RomDatabase::builtin()
+        .lookup(program)
+        .and_then(|rom| rom.config.emulation.quirks)
+        .unwrap_or_else(|| analyser::analyse(program).platform.quirks())
+}
+
+fn sprites_command(args: &SpritesArgs) -> Result<()> {
+    let program = &args.program_file;
+    let database = RomDatabase::builtin();
+    let layers = ConfigLayers::load(args.settings.config.as_deref(), command_line_layer(&args.settings, None))?;
+    let config = layers.resolve(rom_info(program, &database))?;
+
+    let mut emulator = power_on(program, &config.emulation)?;
+    for _ in 0..args.frames {
+        emulator.cycle(0)?;
+    }
+
+    let end = args.to.map_or(chip8::LOADING_POINT + emulator.program_len(), |to| to + 1);
+    let sheet = SpriteSheet::new(args.from, args.height as usize);
+    let rows = sheet.rows(end);
+    let size = sheet.size(rows);
+    let mut pixels = vec![0; size.0 * size.1 * 3];
+    sheet.render(emulator.memory(), end, emulator.i() as usize, rows, &mut pixels, size.0 * 3);
+
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => PathBuf::from(format!("{}-sprites.png", program.file_stem().unwrap_or_default().to_string_lossy())),
+    };
+    screenshot::save_rgb_png(&output, &pixels, size, args.image_scale)?;
+    println!("Saved {:03X}-{:03X} as {} line sprites to {}", sheet.start, end.max(sheet.start + 1) - 1, sheet.height, output.display());
+    Ok(())
+}
+
+fn info_command(args: &InfoArgs) -> Result<()> {
+    let program = std::fs::read(&args.program_file)
+        .map_err(|err| format!("Cannot read {}: {}", args.program_file.display(), err))?;
+    let database = RomDatabase::builtin();
+    let rom = database.lookup(&program);
+    let analysis = analyser::analyse(&program);
+    // the ROM database knows better than a guess from the opcodes
+    let quirks = rom
+        .and_then(|rom| rom.config.emulation.quirks)
+        .unwrap_or(analysis.platform.quirks());
+
+    if args.json {
+        let report = InfoReport {
+            file: args.program_file.display().to_string(),
+            size: program.len(),
+            sha1: romdb::rom_hash(&program),
+            title: rom.map(|rom| rom.title.clone()),
+            description: rom.map(|rom| rom.description.clone()).filter(|description| !description.is_empty()),
+            settings: rom.map(|rom| rom.config.table()).transpose()?.filter(|settings| !settings.is_empty()),
+            quirks: quirks.to_string(),
+            analysis,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!("file         {}", args.program_file.display());
+    println!("size         {} bytes", program.len());
+    println!("sha1         {}", romdb::rom_hash(&program));
+    match &analysis.evidence {
+        Some(evidence) => println!("platform     {} ({} at {:03X})", analysis.platform, evidence.opcode, evidence.address),
+        None => println!("platform     {}", analysis.platform),
+    }
+    println!("quirks       {}", quirks);
+
+    match rom {
+        Some(info) => {
+            println!("title        {}", info.title);
+            if !info.description.is_empty() {
+                println!("description  {}", info.description);
+            }
+            if info.config != ConfigLayer::default() {
+                println!("settings");
+                for line in toml::to_string(&info.config.table()?)?.lines().filter(|line| !line.is_empty()) {
+                    println!("  {}", line);
+                }
+            }
+        }
+        None => println!("title        not in the ROM database"),
+    }
+
+    let unreachable: usize = analysis.unreachable.iter().map(|span| span.bytes).sum();
+    println!("code         {} bytes, {} bytes unreachable", analysis.code_bytes, unreachable);
+    println!("sprites      {}", analysis.sprites.len());
+    let addresses = |addresses: Vec<u16>| addresses.iter().map(|addr| format!("{:03X}", addr)).collect::<Vec<_>>().join(" ");
+    if !analysis.self_modifying.is_empty() {
+        println!("writes code  at {}", addresses(analysis.self_modifying.iter().map(|store| store.address).collect()));
+    }
+    if !analysis.sys_calls.is_empty() {
+        println!("0nnn calls   at {}", addresses(analysis.sys_calls.iter().map(|call| call.address).collect()));
+    }
+    if !analysis.invalid.is_empty() {
+        println!("invalid      at {}", addresses(analysis.invalid.iter().map(|found| found.address).collect()));
+    }
+
+    Ok(())
+}
+
+// What `info --json` prints
+#[derive(serde::Serialize)]
+struct InfoReport {
+    file: String,
+    size: usize,
+    sha1: String,
+    // the ROM database entry
+    title: Option<String>,
+    description: Option<String>,
+    settings: Option<toml::Table>,
+    // the quirks to play it with, from the ROM database or the platform
+    quirks: String,
+    analysis: analyser::Report,
+}
+
+// Runs `program` in the window with the tools and captures asked for on the
+// command line, then writes their reports
+fn play(args: &RunArgs, program: &Path, database: &RomDatabase, frontend: &mut Frontend, from_launcher: bool, config: &Config) -> Result<Exit> {
     // The emulator core
     // here load it with the parsed argument - game + scale
-    let mut emulator = Chip8::new(cli.program_file)?;
+    let mut emulator = power_on(program, &config.emulation)?;
+
+    let mut tools = Tools {
+        cheats: load_cheats(&args.cheats, &mut emulator)?,
+        analysis: Analysis::new(&args.analysis),
+        ..Tools::default()
+    };
 
-    if let Some(scale) = cli.scale {
-        run(&mut emulator, scale as u32)
+    if let Some(port) = args.gdb {
+        println!("Waiting for gdb on localhost:{}", port);
+        tools.gdb = Some(GdbStub::bind(port)?);
     }
-    else {
-        run (&mut emulator, SCALE)
+
+    if config.frontend == FrontendKind::Tui {
+        if args.gdb.is_some() {
+            return Err("The terminal debugger cannot be used with --gdb".into());
+        }
+        tools.tui = Some(TuiDebugger::new(&emulator)?);
     }
+
+    if let Some(path) = &args.script {
+        if config.frontend == FrontendKind::Tui {
+            return Err("Scripts cannot be run with the terminal debugger".into());
+        }
+        tools.script = Some(Script::load(path, &mut emulator, config.display.palette)?);
+    }
+
+    tools.analysis.heatmap = args.heatmap.then(Heatmap::new);
+    tools.sprites = args.sprites.then(SpriteViewer::new);
+
+    let mut capture = Capture::new(&args.capture, &config.display.palette)?;
+
+    frontend.set_title(&window_title(program, database))?;
+    let mut game = Game {
+        program: program.to_path_buf(),
+        emulator,
+        settings: config.emulation,
+        database,
+        watcher: args.watch.then(|| FileWatcher::new(program)),
+        watch_keep: args.watch_keep,
+    };
+    let result = run(frontend, &mut game, config, &mut tools, &mut capture, from_launcher);
+
+    // leave the terminal before reporting anything
+    tools.tui = None;
+
+    capture.recording.finish()?;
+    tools.analysis.write_reports(&game.emulator)?;
+
+    result
 }
 
-fn run(emulator: &mut Chip8, scale: u32) -> Result<()> {
-    // Set up the Front-end of the emulator using SDL-2
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
-    let audio_subsystem = sdl_context.audio()?;
+// Runs the emulator without any window, sound or keys
+fn run_headless(emulator: &mut Chip8, frames: u64, config: &Config, cheats: &Cheats, script: &mut Option<Script>, analysis: &mut Analysis, capture: &mut Capture) -> Result<()> {
+    let palette = config.display.palette;
+    let mut phosphor = Phosphor::new(config.display.persistence);
 
-    let audio = audio_subsystem.open_playback(None, &DESIRED_AUDIO_SPEC, |spec| {
-        // initialize the audio callback
-        SquareWave {
-            phase_inc: 440.0 / spec.freq as f32,
-            phase: 0.0,
-            volume: 0.25,
+    for frame in 1..=frames {
+        cheats.apply(emulator);
+        match script.as_mut() {
+            Some(script) => script.run_frame(emulator, 0, &mut analysis.tracers())?,
+            None => emulator.cycle_traced(0, &mut analysis.tracers())?,
         }
+
+        phosphor.update(&emulator.gfx());
+        let events = emulator.take_buzzer_events();
+        capture.recording.capture(&phosphor, &events, emulator.emulated_time(), &config.buzzer)?;
+
+        if let Some(screenshot) = &capture.screenshot {
+            if screenshot.frame.unwrap_or(frames) == frame {
+                screenshot::save_png(&screenshot.path, &emulator.gfx(), screenshot.scale, &palette)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn run(frontend: &mut Frontend, game: &mut Game, config: &Config, tools: &mut Tools, capture: &mut Capture, from_launcher: bool) -> Result<Exit> {
+    let display = &config.display;
+    let mut buzzer = config.buzzer;
+    let scale = display.scale;
+    let mut palette = display.palette;
+    let Game { program, emulator, settings, database, watcher, watch_keep } = game;
+    let initial_settings = *settings;
+
+    // the buzzer is rendered after every frame and played from the ring
+    let ring = SampleRing::default();
+    let audio = frontend.audio_subsystem.open_playback(None, &DESIRED_AUDIO_SPEC, |spec| {
+        // initialize the audio callback
+        RingPlayer::new(ring.clone(), &spec)
     })?;
+    let mut sound = SoundRenderer::new(buzzer, audio.spec().freq);
+    let mut samples = Vec::new();
+    audio.resume();
 
-    let window = video_subsystem.window("chip-8 emulator",
-        chip8::SCREEN_WIDTH as u32 * scale,
-        chip8::SCREEN_HEIGTH as u32 * scale,
-        )
-        .position_centered()
-        .build()
-        .unwrap();
- 
     // Graphics related things
-    let mut canvas = window.into_canvas().build().unwrap();
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.present();
-
-    let texture_creator = canvas.texture_creator();
-    let mut tex_display = texture_creator
-        .create_texture_streaming(
-            PixelFormatEnum::RGB24,
-            chip8::SCREEN_WIDTH as u32,
-            chip8::SCREEN_HEIGTH as u32,
-        )
-        .map_err(|e| e.to_string())?;
-
-    // For getting the keyboard events...
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    let [r, g, b] = palette.background();
+    frontend.canvas.set_draw_color(Color::RGB(r, g, b));
+    frontend.canvas.clear();
+    frontend.canvas.present();
+
+    let mut phosphor = Phosphor::new(display.persistence);
+
+    // the textures follow the resolution of the picture, and for the
+    // filters the scale it is shown at
+    let texture_creator = frontend.canvas.texture_creator();
+    let mut tex_display = streaming_texture(&texture_creator, phosphor.resolution())?;
+
+    let mut osd = Osd::new(display.osd, tools.tui.is_none());
+    let mut menu = PauseMenu::new(settings.quirks, settings.speed, palette, from_launcher);
+
+    let mut filter = display.filter;
+    let mut filter_renderer = FilterRenderer::new(phosphor.resolution(), scale);
+    let mut tex_filtered = streaming_texture(&texture_creator, filter_renderer.frame_size())?;
+    let mut rgb = Vec::new();
+
+    // Secondary window with the memory heatmap
+    let mut heatmap_canvas = match tools.analysis.heatmap {
+        Some(_) => {
+            let window = frontend.video_subsystem.window("chip-8 memory heatmap",
+                HEATMAP_COLUMNS as u32 * HEATMAP_SCALE,
+                HEATMAP_ROWS as u32 * HEATMAP_SCALE,
+                )
+                .build()?;
+            Some(window.into_canvas().build()?)
+        }
+        None => None,
+    };
+    let heatmap_texture_creator = heatmap_canvas.as_ref().map(|canvas| canvas.texture_creator());
+    let mut tex_heatmap = heatmap_texture_creator
+        .as_ref()
+        .map(|texture_creator| streaming_texture(texture_creator, (HEATMAP_COLUMNS as u32, HEATMAP_ROWS as u32)))
+        .transpose()?;
+
+    // Secondary window with memory as sprites
+    let mut sprites_canvas = match tools.sprites {
+        Some(_) => {
+            let window = frontend.video_subsystem.window("chip-8 sprites",
+                SHEET_WIDTH as u32 * SPRITES_SCALE,
+                PAGE_HEIGHT as u32 * SPRITES_SCALE,
+                )
+                .build()?;
+            Some(window.into_canvas().build()?)
+        }
+        None => None,
+    };
+    let sprites_texture_creator = sprites_canvas.as_ref().map(|canvas| canvas.texture_creator());
+    let mut tex_sprites = sprites_texture_creator
+        .as_ref()
+        .map(|texture_creator| streaming_texture(texture_creator, (SHEET_WIDTH as u32, PAGE_HEIGHT as u32)))
+        .transpose()?;
 
     let frame_duration = Duration::new(0, 1_000_000_000u32 / 60);
     let mut timestamp = Instant::now();
 
     let mut key = 0u16;
+    let mut frame = 0u64;
+    // emulated frames owed to the speed, run once they add up to one
+    let mut frame_credit = 0.0;
+    // size of a CHIP-8 pixel on screen, as last presented
+    let mut window_scale = scale;
 
-    'running: loop {
+    let exit = 'running: loop {
         // Key handling
-        for event in event_pump.poll_iter() {
+        for event in frontend.poll_events() {
+            if menu.open {
+                let start = matches!(event, Event::ControllerButtonDown { button: Button::Start, .. });
+                let action = match frontend::menu_input(&event) {
+                    Some(input) => menu.input(input),
+                    None if start => Some(MenuAction::Resume),
+                    None => None,
+                };
+
+                // the settings apply as soon as they change
+                settings.quirks = menu.quirks;
+                settings.speed = menu.speed;
+                emulator.set_quirks(settings.quirks.quirks());
+                if palette != menu.palette {
+                    palette = menu.palette;
+                    let [r, g, b] = palette.background();
+                    frontend.canvas.set_draw_color(Color::RGB(r, g, b));
+                }
+
+                match action {
+                    Some(MenuAction::Resume) => menu.open = false,
+                    Some(MenuAction::Reset) => {
+                        match power_on(program, settings) {
+                            Ok(machine) => {
+                                *emulator = machine;
+                                resync_sound(emulator, &mut sound, &mut capture.recording);
+                                osd.message("Reset");
+                            }
+                            Err(err) => osd.message(format!("Cannot reset: {}", err)),
+                        }
+                        menu.open = false;
+                    }
+                    Some(MenuAction::SaveState(slot)) => {
+                        let path = state_file_name(program, slot);
+                        match std::fs::write(&path, emulator.save_state()) {
+                            Ok(()) => osd.message(format!("State saved to slot {}", slot)),
+                            Err(err) => osd.message(format!("Cannot save to {}: {}", path.display(), err)),
+                        }
+                        menu.open = false;
+                    }
+                    Some(MenuAction::LoadState(slot)) => {
+                        let path = state_file_name(program, slot);
+                        let loaded = std::fs::read(&path)
+                            .map_err(Error::from)
+                            .and_then(|state| emulator.load_state(&state).map_err(Error::from));
+                        match loaded {
+                            Ok(()) => {
+                                resync_sound(emulator, &mut sound, &mut capture.recording);
+                                osd.message(format!("State loaded from slot {}", slot));
+                            }
+                            Err(err) => osd.message(format!("Cannot load slot {}: {}", slot, err)),
+                        }
+                        menu.open = false;
+                    }
+                    Some(MenuAction::Launcher) => break 'running Exit::Menu,
+                    Some(MenuAction::Quit) => break 'running Exit::Quit,
+                    None => {}
+                }
+
+                if !matches!(event, Event::Quit { .. } | Event::Window { .. } | Event::DropFile { .. }) {
+                    continue;
+                }
+            }
+
             match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
+                Event::Quit { .. } => break 'running Exit::Quit,
+                // a ROM dropped on the window replaces the running one
+                Event::DropFile { filename, .. } => {
+                    let dropped = PathBuf::from(filename);
+                    match power_on(&dropped, settings) {
+                        Ok(machine) => {
+                            *emulator = machine;
+                            *program = dropped;
+                            tools.cheats.switch_program(emulator.program());
+                            resync_sound(emulator, &mut sound, &mut capture.recording);
+                            frontend.set_title(&window_title(program, database))?;
+                            if let Some(watcher) = watcher.as_mut() {
+                                watcher.watch(program);
+                            }
+                            osd.message(format!("Loaded {}", program.display()));
+                        }
+                        Err(err) => osd.message(format!("Cannot load {}: {}", dropped.display(), err)),
+                    }
+                    menu.open = false;
+                }
+                // Esc or the Start button pause the game
+                Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
-                } => break 'running,
+                }
+                | Event::ControllerButtonDown {
+                    button: Button::Start,
+                    ..
+                } => {
+                    menu.open();
+                    key = 0;
+                }
+                // with a second window open, closing one of them is not a Quit
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } => {
+                    if window_id == frontend.canvas.window().id() {
+                        break 'running Exit::Quit;
+                    }
+                    for canvas in [heatmap_canvas.as_mut(), sprites_canvas.as_mut()].into_iter().flatten() {
+                        if canvas.window().id() == window_id {
+                            canvas.window_mut().hide();
+                        }
+                    }
+                }
+                // the keys pressed in the sprite window move through memory
                 Event::KeyDown {
+                    window_id,
                     keycode: Some(keycode),
                     ..
+                } if sprites_canvas.as_ref().is_some_and(|canvas| canvas.window().id() == window_id) => {
+                    if let Some(viewer) = tools.sprites.as_mut() {
+                        viewer.key(keycode, emulator.i() as usize);
+                    }
+                }
+                // F12 saves a screenshot at the window scale, Shift+F12 at 64x32
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    keymod,
+                    ..
                 } => {
-                    key |= match keycode {
-                        Keycode::Num1 => 1 << 0x1,
-                        Keycode::Num2 => 1 << 0x2,
-                        Keycode::Num3 => 1 << 0x3,
-                        Keycode::Num4 => 1 << 0xC,
-                        Keycode::Q => 1 << 0x4,
-                        Keycode::W => 1 << 0x5,
-                        Keycode::E => 1 << 0x6,
-                        Keycode::R => 1 << 0xD,
-                        Keycode::A => 1 << 0x7,
-                        Keycode::S => 1 << 0x8,
-                        Keycode::D => 1 << 0x9,
-                        Keycode::F => 1 << 0xE,
-                        Keycode::Z => 1 << 0xA,
-                        Keycode::X => 1 << 0x0,
-                        Keycode::C => 1 << 0xB,
-                        Keycode::V => 1 << 0xF,
-                        _ => 0,
-                    };
+                    let raw = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    let path = capture_file_name("screenshot", frame, "png");
+                    match screenshot::save_png(&path, &emulator.gfx(), if raw { 1 } else { window_scale }, &palette) {
+                        Ok(()) => osd.message(format!("Screenshot saved to {}", path.display())),
+                        Err(err) => osd.message(format!("Cannot save {}: {}", path.display(), err)),
+                    }
+                }
+                // F5 switches the waveform, F6 mutes, F7/F8 change the volume
+                // and with Shift the pitch, by a semitone
+                Event::KeyDown {
+                    keycode: Some(keycode @ (Keycode::F5 | Keycode::F6 | Keycode::F7 | Keycode::F8)),
+                    keymod,
+                    ..
+                } => {
+                    let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    match keycode {
+                        Keycode::F5 => {
+                            buzzer.waveform = buzzer.waveform.next();
+                            osd.message(format!("Waveform: {}", buzzer.waveform));
+                        }
+                        Keycode::F6 => {
+                            buzzer.muted = !buzzer.muted;
+                            osd.message(format!("Sound {}", if buzzer.muted { "muted" } else { "on" }));
+                        }
+                        _ if shift => {
+                            let semitones = if keycode == Keycode::F7 { -1.0 } else { 1.0 };
+                            buzzer.frequency = (buzzer.frequency * 2f32.powf(semitones / 12.0)).clamp(config::MIN_TONE, config::MAX_TONE);
+                            osd.message(format!("Pitch: {:.0} Hz", buzzer.frequency));
+                        }
+                        _ => {
+                            let step = if keycode == Keycode::F7 { -0.05 } else { 0.05 };
+                            buzzer.volume = (buzzer.volume + step).clamp(0.0, 1.0);
+                            osd.message(format!("Volume: {:.0}%", buzzer.volume * 100.0));
+                        }
+                    }
+                    sound.buzzer.settings = buzzer;
+                }
+                // F11 toggles fullscreen
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => {
+                    frontend.toggle_fullscreen()?;
+                }
+                // F3 shows and hides the overlay
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => osd.visible = !osd.visible,
+                // F9 switches to the next filter
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => {
+                    filter = filter.next();
+                    osd.message(format!("Filter: {}", filter));
+                }
+                // F10 starts and stops a GIF recording
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } => {
+                    if let Some(gif) = capture.recording.gif.take() {
+                        match gif.finish() {
+                            Ok(()) => osd.message("Recording stopped"),
+                            Err(err) => osd.message(format!("Cannot finish the recording: {}", err)),
+                        }
+                    }
+                    else {
+                        let path = capture.gif_path.clone().unwrap_or_else(|| capture_file_name("recording", frame, "gif"));
+                        match GifRecorder::create(&path, capture.record_scale, &palette) {
+                            Ok(gif) => {
+                                capture.recording.gif = Some(gif);
+                                osd.message(format!("Recording to {}", path.display()));
+                            }
+                            Err(err) => osd.message(format!("Cannot record to {}: {}", path.display(), err)),
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    if let Some(pressed) = config.keymap.key(keycode) {
+                        key |= 1 << pressed;
+                    }
                 }
                 Event::KeyUp {
                     keycode: Some(keycode),
                     ..
                 } => {
-                    key &= !match keycode {
-                        Keycode::Num1 => 1 << 0x1,
-                        Keycode::Num2 => 1 << 0x2,
-                        Keycode::Num3 => 1 << 0x3,
-                        Keycode::Num4 => 1 << 0xC,
-                        Keycode::Q => 1 << 0x4,
-                        Keycode::W => 1 << 0x5,
-                        Keycode::E => 1 << 0x6,
-                        Keycode::R => 1 << 0xD,
-                        Keycode::A => 1 << 0x7,
-                        Keycode::S => 1 << 0x8,
-                        Keycode::D => 1 << 0x9,
-                        Keycode::F => 1 << 0xE,
-                        Keycode::Z => 1 << 0xA,
-                        Keycode::X => 1 << 0x0,
-                        Keycode::C => 1 << 0xB,
-                        Keycode::V => 1 << 0xF,
-                        _ => 0,
-                    };
+                    if let Some(released) = config.keymap.key(keycode) {
+                        key &= !(1 << released);
+                    }
                 }
                 _ => {}
             }
         }
 
-        // Pass it to our emulator and execute opcode
-        emulator.cycle(key)?;
+        // --watch restarts the machine once the program changed on disk
+        if watcher.as_mut().is_some_and(|watcher| watcher.changed()) {
+            if !*watch_keep {
+                *settings = initial_settings;
+                menu.quirks = settings.quirks;
+                menu.speed = settings.speed;
+                key = 0;
+            }
+            match power_on(program, settings) {
+                Ok(machine) => {
+                    *emulator = machine;
+                    resync_sound(emulator, &mut sound, &mut capture.recording);
+                    osd.message(format!("{} changed, restarted", program.display()));
+                }
+                Err(err) => osd.message(format!("Cannot reload {}: {}", program.display(), err)),
+            }
+        }
 
-        // Audio
-        if emulator.tone() {
-            audio.resume()
+        // Run as many frames as the speed asks for, none while paused
+        if !menu.open {
+            frame_credit += settings.speed;
         }
-        else {
-            audio.pause();
+        samples.clear();
+        while frame_credit >= 1.0 {
+            frame_credit -= 1.0;
+
+            // Pass it to our emulator and execute opcode
+            let mut tracers = tools.analysis.tracers();
+            tools.cheats.apply(emulator);
+
+            if let Some(gdb) = tools.gdb.as_mut() {
+                gdb.run_frame(emulator, key, &mut tracers)?;
+                if gdb.killed() {
+                    break 'running Exit::Quit;
+                }
+            }
+            else if let Some(tui) = tools.tui.as_mut() {
+                tui.run_frame(emulator, key, &mut tracers, &mut tools.cheats)?;
+                if tui.quit() {
+                    break 'running Exit::Quit;
+                }
+            }
+            else if let Some(script) = tools.script.as_mut() {
+                script.run_frame(emulator, key, &mut tracers)?;
+            }
+            else if tracers.is_empty() {
+                emulator.cycle(key)?;
+            }
+            else {
+                emulator.cycle_traced(key, &mut tracers)?;
+            }
+
+            // Audio, played faster or slower with the speed
+            let events = emulator.take_buzzer_events();
+            sound.render(&events, emulator.emulated_time(), settings.speed, &mut samples);
+
+            phosphor.update(&emulator.gfx());
+            capture.recording.capture(&phosphor, &events, emulator.emulated_time(), &buzzer)?;
+
+            frame += 1;
+            if let Some(screenshot) = &capture.screenshot {
+                if screenshot.frame == Some(frame) {
+                    screenshot::save_png(&screenshot.path, &emulator.gfx(), screenshot.scale, &palette)?;
+                }
+            }
         }
+        ring.push(&samples, audio::queue_limit(audio.spec(), settings.speed));
 
         // Draw graphics
-        tex_display.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-            for y in 0..chip8::SCREEN_HEIGTH {
-                for x in 0..chip8::SCREEN_WIDTH / 8 {
-                    let byte = emulator.gfx()[y * chip8::SCREEN_WIDTH / 8 + x];
-                    for i in 0..8 {
-                        let offset = y * pitch + (x * 8 + i) * 3;
-                        let on = if byte & 1 << (7 - i) != 0 {
-                            true
-                        } else {
-                            false
-                        };
-                        const FACTOR: u8 = 30;
-                        let v = if on {
-                            255
-                        } else {
-                            buffer[offset].saturating_sub(FACTOR)
-                        };
-                        buffer[offset] = v;
-                        buffer[offset + 1] = v;
-                        buffer[offset + 2] = v;
-                    }
-                }
+        let resolution = phosphor.resolution();
+        let viewport = display.scaling.viewport(frontend.canvas.output_size()?, resolution);
+        window_scale = (viewport.height() / resolution.1).max(1);
+
+        frontend.canvas.clear();
+        if filter.is_none() {
+            let query = tex_display.query();
+            if (query.width, query.height) != resolution {
+                tex_display = streaming_texture(&texture_creator, resolution)?;
+            }
+            tex_display.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                phosphor.write_rgb(&palette, buffer, pitch);
+            })?;
+            frontend.canvas.copy(&tex_display, None, viewport)?;
+        }
+        else {
+            // filtered at the whole scale of the viewport, for even scanlines
+            if !filter_renderer.renders(resolution, window_scale) {
+                filter_renderer = FilterRenderer::new(resolution, window_scale);
+                tex_filtered = streaming_texture(&texture_creator, filter_renderer.frame_size())?;
             }
-        })?;
+            rgb.resize((resolution.0 * resolution.1 * 3) as usize, 0);
+            phosphor.write_rgb(&palette, &mut rgb, resolution.0 as usize * 3);
+            tex_filtered.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                filter_renderer.render(filter, &rgb, buffer, pitch);
+            })?;
+            frontend.canvas.copy(&tex_filtered, None, viewport)?;
+        }
+        if menu.open {
+            menu.draw(&mut frontend.canvas)?;
+        }
+        osd.draw(&mut frontend.canvas, emulator.keypad())?;
+        frontend.canvas.present();
+        osd.frame_presented(emulator);
 
-        canvas.clear();
-        canvas.copy(&tex_display, None, None)?;
-        canvas.present();
+        if let (Some(heatmap), Some(heatmap_canvas), Some(tex_heatmap)) = (&tools.analysis.heatmap, heatmap_canvas.as_mut(), tex_heatmap.as_mut()) {
+            tex_heatmap.with_lock(None, |buffer: &mut [u8], pitch: usize| heatmap.render(buffer, pitch))?;
+            heatmap_canvas.copy(tex_heatmap, None, None)?;
+            heatmap_canvas.present();
+        }
+
+        if let (Some(viewer), Some(sprites_canvas), Some(tex_sprites)) = (&tools.sprites, sprites_canvas.as_mut(), tex_sprites.as_mut()) {
+            let title = viewer.title();
+            if sprites_canvas.window().title() != title {
+                sprites_canvas.window_mut().set_title(&title)?;
+            }
+            tex_sprites.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+                viewer.render(emulator.memory(), emulator.i() as usize, buffer, pitch)
+            })?;
+            sprites_canvas.copy(tex_sprites, None, None)?;
+            sprites_canvas.present();
+        }
 
         // FPS
         let now = Instant::now();
@@ -201,7 +1314,130 @@ fn run(emulator: &mut Chip8, scale: u32) -> Result<()> {
             .unwrap_or(Duration::new(0, 0));
         ::std::thread::sleep(sleep_dur);
         timestamp = now;
+    };
+
+    Ok(exit)
+}
+
+// RGB24 texture updated every frame
+pub fn streaming_texture(texture_creator: &TextureCreator<WindowContext>, (width, height): (u32, u32)) -> Result<Texture<'_>> {
+    Ok(texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width, height)?)
+}
+
+// A machine just powered on with `program`
+fn power_on(program: &Path, settings: &EmulationSettings) -> Result<Chip8> {
+    let mut emulator = Chip8::new(program.to_path_buf())?;
+    emulator.set_quirks(settings.quirks.quirks());
+    Ok(emulator)
+}
+
+// The cheats saved for the program of `emulator`, with the ones named on the
+// command line on
+fn load_cheats(names: &[String], emulator: &mut Chip8) -> Result<Cheats> {
+    let mut cheats = Cheats::load(emulator.program())?;
+    for name in names {
+        cheats.enable(name, emulator)?;
     }
+    Ok(cheats)
+}
 
-    Ok(())
+// Carries the sound on after the machine jumped in emulated time
+fn resync_sound(emulator: &Chip8, sound: &mut SoundRenderer, recording: &mut Recording) {
+    let on = emulator.sound_timer() > 0;
+    sound.resync(emulator.emulated_time(), on);
+    recording.resync(emulator.emulated_time(), on);
+}
+
+// The entry of `program` in the database, if it can be read and is known
+fn rom_info<'a>(program: &Path, database: &'a RomDatabase) -> Option<&'a RomInfo> {
+    std::fs::read(program).ok().and_then(|bytes| database.lookup(&bytes))
+}
+
+// The window title, with the name of the program if the database knows it
+fn window_title(program: &Path, database: &RomDatabase) -> String {
+    match rom_info(program, database) {
+        Some(info) => format!("{} - {}", frontend::WINDOW_TITLE, info.title),
+        None => frontend::WINDOW_TITLE.to_string(),
+    }
+}
+
+// The settings given on the command line, over the configuration file
+fn command_line_layer(cli: &SettingsArgs, frontend: Option<FrontendKind>) -> ConfigLayer {
+    ConfigLayer {
+        frontend,
+        display: DisplayLayer {
+            scale: cli.scale,
+            scaling: cli.scaling,
+            fullscreen: cli.fullscreen,
+            palette: cli.palette,
+            persistence: cli.persistence,
+            filter: cli.filter,
+            osd: cli.osd,
+        },
+        emulation: EmulationLayer {
+            quirks: cli.quirks,
+            speed: cli.speed,
+        },
+        audio: AudioLayer {
+            tone: cli.tone,
+            waveform: cli.waveform,
+            volume: cli.volume,
+            mute: cli.mute,
+        },
+        keys: cli.keys.iter().cloned().collect::<BTreeMap<_, _>>(),
+    }
+}
+
+// <program name>.state<slot> in the working directory
+fn state_file_name(program: &Path, slot: usize) -> PathBuf {
+    let name = program.file_stem().unwrap_or_default().to_string_lossy();
+    PathBuf::from(format!("{}.state{}", name, slot))
+}
+
+// A memory address, in hex with or without # or 0x
+fn parse_address(s: &str) -> std::result::Result<usize, String> {
+    let digits = s.strip_prefix('#').or_else(|| s.strip_prefix("0x")).unwrap_or(s);
+    match usize::from_str_radix(digits, 16) {
+        Ok(addr) if addr < chip8::MEMORY_SIZE => Ok(addr),
+        Ok(_) => Err(format!("#{} is past the end of memory", digits)),
+        Err(_) => Err(format!("'{}' is not a hex address", s)),
+    }
+}
+
+fn parse_speed(s: &str) -> std::result::Result<f64, String> {
+    let speed: f64 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    config::check_speed(speed)
+}
+
+fn parse_record_scale(s: &str) -> std::result::Result<u32, String> {
+    let scale: u32 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if !(1..=recording::MAX_SCALE).contains(&scale) {
+        return Err(format!("scale {} is not between 1 and {}", scale, recording::MAX_SCALE));
+    }
+    Ok(scale)
+}
+
+fn parse_tone(s: &str) -> std::result::Result<f32, String> {
+    let tone: f32 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    config::check_tone(tone)
+}
+
+fn parse_volume(s: &str) -> std::result::Result<f32, String> {
+    let volume: f32 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    config::check_volume(volume)
+}
+
+// KEY=NAME, a CHIP-8 key and the keyboard key pressing it
+fn parse_binding(s: &str) -> std::result::Result<(String, String), String> {
+    let (key, name) = s.split_once('=').ok_or_else(|| format!("'{}' is not KEY=NAME", s))?;
+    Ok((key.trim().to_string(), name.trim().to_string()))
+}
+
+// <kind>-<unix time>-<frame>.<extension> in the working directory
+fn capture_file_name(kind: &str, frame: u64, extension: &str) -> PathBuf {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    PathBuf::from(format!("{}-{}-{}.{}", kind, seconds, frame, extension))
 }
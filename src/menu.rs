@@ -0,0 +1,207 @@
+// Pause menu, opened with Esc or the Start button of a gamepad
+//
+// The game stops while the menu is open. Up and down pick an entry, left and
+// right change the value of the settings and of the save state slot, and
+// select runs the entry. The settings take effect as soon as they change.
+
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::font::{self, GLYPH_HEIGHT, LINE_HEIGHT};
+use crate::osd::{Painter, INVERTED_TEXT, PANEL, TEXT};
+use crate::palette::Palette;
+use crate::quirks::QuirkPreset;
+
+pub const SAVE_SLOTS: usize = 8;
+
+// Speeds picked from in the menu, as multiples of the real CHIP-8 speed
+pub const SPEEDS: [f64; 7] = [0.25, 0.5, 1.0, 1.5, 2.0, 4.0, 8.0];
+
+// Window height drawn per font pixel
+const HEIGHT_PER_PIXEL: u32 = 160;
+
+// Space around the entries, in font pixels
+const PADDING: i32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+// What the game has to do after an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    Resume,
+    Reset,
+    SaveState(usize),
+    LoadState(usize),
+    Launcher,
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    Resume,
+    Reset,
+    SaveState,
+    LoadState,
+    Quirks,
+    Speed,
+    Palette,
+    Launcher,
+    Quit,
+}
+
+pub struct PauseMenu {
+    pub open: bool,
+    entries: Vec<Entry>,
+    selected: usize,
+
+    // settings changed from the menu
+    pub slot: usize,
+    pub quirks: QuirkPreset,
+    pub speed: f64,
+    pub palette: Palette,
+}
+
+impl PauseMenu {
+    // `launcher` adds an entry going back to the ROM list
+    pub fn new(quirks: QuirkPreset, speed: f64, palette: Palette, launcher: bool) -> Self {
+        let mut entries = vec![
+            Entry::Resume,
+            Entry::Reset,
+            Entry::SaveState,
+            Entry::LoadState,
+            Entry::Quirks,
+            Entry::Speed,
+            Entry::Palette,
+        ];
+        if launcher {
+            entries.push(Entry::Launcher);
+        }
+        entries.push(Entry::Quit);
+
+        PauseMenu {
+            open: false,
+            entries,
+            selected: 0,
+            slot: 1,
+            quirks,
+            speed,
+            palette,
+        }
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+        self.selected = 0;
+    }
+
+    pub fn input(&mut self, input: MenuInput) -> Option<MenuAction> {
+        let entry = self.entries[self.selected];
+        let count = self.entries.len();
+
+        match input {
+            MenuInput::Up => self.selected = (self.selected + count - 1) % count,
+            MenuInput::Down => self.selected = (self.selected + 1) % count,
+            MenuInput::Left => self.change(entry, false),
+            MenuInput::Right => self.change(entry, true),
+            MenuInput::Back => return Some(MenuAction::Resume),
+            MenuInput::Select => {
+                return match entry {
+                    Entry::Resume => Some(MenuAction::Resume),
+                    Entry::Reset => Some(MenuAction::Reset),
+                    Entry::SaveState => Some(MenuAction::SaveState(self.slot)),
+                    Entry::LoadState => Some(MenuAction::LoadState(self.slot)),
+                    Entry::Launcher => Some(MenuAction::Launcher),
+                    Entry::Quit => Some(MenuAction::Quit),
+                    Entry::Quirks | Entry::Speed | Entry::Palette => {
+                        self.change(entry, true);
+                        None
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn change(&mut self, entry: Entry, forward: bool) {
+        match entry {
+            Entry::SaveState | Entry::LoadState => {
+                self.slot = if forward { self.slot % SAVE_SLOTS + 1 } else { (self.slot + SAVE_SLOTS - 2) % SAVE_SLOTS + 1 };
+            }
+            Entry::Quirks => {
+                self.quirks = if forward { self.quirks.next() } else { self.quirks.previous() };
+            }
+            Entry::Speed => {
+                // the next speed of the list, from wherever the speed is
+                let next = if forward {
+                    SPEEDS.iter().find(|&&speed| speed > self.speed)
+                }
+                else {
+                    SPEEDS.iter().rev().find(|&&speed| speed < self.speed)
+                };
+                if let Some(speed) = next {
+                    self.speed = *speed;
+                }
+            }
+            Entry::Palette => {
+                self.palette = if forward { self.palette.next() } else { self.palette.previous() };
+            }
+            _ => {}
+        }
+    }
+
+    fn label(&self, entry: Entry) -> String {
+        match entry {
+            Entry::Resume => "RESUME".to_string(),
+            Entry::Reset => "RESET".to_string(),
+            Entry::SaveState => format!("SAVE STATE  < {} >", self.slot),
+            Entry::LoadState => format!("LOAD STATE  < {} >", self.slot),
+            Entry::Quirks => format!("QUIRKS  < {} >", self.quirks),
+            Entry::Speed => format!("SPEED  < {}X >", self.speed),
+            Entry::Palette => format!("PALETTE  < {} >", self.palette.theme().unwrap_or("custom")),
+            Entry::Launcher => "ROM LIST".to_string(),
+            Entry::Quit => "QUIT".to_string(),
+        }
+    }
+
+    // Draws the menu in the middle of the canvas, over a dimmed game
+    pub fn draw(&self, canvas: &mut Canvas<Window>) -> crate::Result<()> {
+        let (width, height) = canvas.output_size()?;
+        let mut painter = Painter::new(canvas, (height / HEIGHT_PER_PIXEL).max(1) as i32);
+        let columns = width as i32 / painter.scale;
+        let rows = height as i32 / painter.scale;
+
+        let title = "PAUSED";
+        let labels: Vec<String> = self.entries.iter().map(|entry| self.label(*entry)).collect();
+        let widest = labels.iter().chain([title.to_string()].iter()).map(|label| font::text_width(label)).max().unwrap_or(0);
+
+        let panel_width = widest as i32 + 2 * PADDING;
+        let panel_height = ((labels.len() + 2) * LINE_HEIGHT) as i32 - (LINE_HEIGHT - GLYPH_HEIGHT) as i32 + 2 * PADDING;
+        let left = (columns - panel_width).max(0) / 2;
+        let top = (rows - panel_height).max(0) / 2;
+
+        painter.fill(0, 0, columns + 1, rows + 1, PANEL)?;
+        painter.fill(left, top, panel_width, panel_height, PANEL)?;
+        painter.text(left + (panel_width - font::text_width(title) as i32) / 2, top + PADDING, title, TEXT)?;
+
+        for (idx, label) in labels.iter().enumerate() {
+            let y = top + PADDING + ((idx + 2) * LINE_HEIGHT) as i32;
+            if idx == self.selected {
+                painter.fill(left + 1, y - 1, panel_width - 2, LINE_HEIGHT as i32, TEXT)?;
+                painter.text(left + PADDING, y, label, INVERTED_TEXT)?;
+            }
+            else {
+                painter.text(left + PADDING, y, label, TEXT)?;
+            }
+        }
+
+        Ok(())
+    }
+}
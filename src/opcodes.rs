@@ -2,7 +2,7 @@
 // Got to look how to assign values to enums
 use enum_stringify::EnumStringify;
 
-#[derive(Debug, EnumStringify)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumStringify)]
 pub enum Opcodes {
     SysExecute = 0x0000,
     ClearScreen = 0x00E0,
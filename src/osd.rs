@@ -0,0 +1,276 @@
+// On-screen display drawn over the game
+//
+// With the overlay shown (`--osd`, F3 toggles it), the top left corner has
+// the frames per second, the instructions executed per second and the speed
+// of the emulation against real time, and the top right corner a map of the
+// CHIP-8 keypad with the held keys lit. Messages such as "Filter: grid" show
+// up at the bottom for a few seconds whether the overlay is on or not.
+//
+// Everything is drawn with the bitmap font as rectangles on the SDL canvas,
+// after the picture, so screenshots and recordings are not affected.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::chip8::Chip8;
+use crate::font::{self, GLYPH_HEIGHT, LINE_HEIGHT};
+
+// How long a message stays on screen and how many are shown at once
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+const MAX_MESSAGES: usize = 4;
+
+// How often the figures are measured
+const SAMPLE_TIME: Duration = Duration::from_millis(500);
+
+// Window height drawn per font pixel, the overlay grows with the window
+const HEIGHT_PER_PIXEL: u32 = 240;
+
+// Space around the text of a panel, in font pixels
+const PADDING: i32 = 2;
+
+// The keypad as laid out on the COSMAC VIP
+const KEYPAD: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// A key of the keypad map: its glyph with 2 pixels around it and 1 between
+// keys
+const KEY_CELL: usize = GLYPH_HEIGHT + 4;
+
+pub const PANEL: Color = Color::RGBA(0, 0, 0, 176);
+pub const TEXT: Color = Color::RGB(0xFF, 0xFF, 0xFF);
+// text on a TEXT coloured background
+pub const INVERTED_TEXT: Color = Color::RGB(0, 0, 0);
+
+// Progress of the emulation at a moment, for measuring rates between two
+struct Sample {
+    at: Instant,
+    frames: u64,
+    instructions: u64,
+    emulated_time: u64,
+}
+
+impl Sample {
+    fn take(frames: u64, emulator: &Chip8) -> Self {
+        Sample {
+            at: Instant::now(),
+            frames,
+            instructions: emulator.instructions(),
+            emulated_time: emulator.emulated_time(),
+        }
+    }
+}
+
+pub struct Osd {
+    pub visible: bool,
+    // whether messages are printed on the console too, not while the
+    // terminal debugger draws on it
+    console: bool,
+
+    frames: u64,
+    sample: Option<Sample>,
+    fps: f64,
+    instructions_per_second: f64,
+    speed: f64,
+
+    messages: VecDeque<(String, Instant)>,
+}
+
+impl Osd {
+    pub fn new(visible: bool, console: bool) -> Self {
+        Osd {
+            visible,
+            console,
+            frames: 0,
+            sample: None,
+            fps: 0.0,
+            instructions_per_second: 0.0,
+            speed: 0.0,
+            messages: VecDeque::new(),
+        }
+    }
+
+    // Shows `text` for a while, it is printed on the console as well
+    pub fn message(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        if self.console {
+            println!("{}", text);
+        }
+
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((text, Instant::now()));
+    }
+
+    // Counts a presented frame, `emulator` having run up to it
+    pub fn frame_presented(&mut self, emulator: &Chip8) {
+        self.frames += 1;
+
+        let sample = match &self.sample {
+            Some(sample) => sample,
+            None => {
+                self.sample = Some(Sample::take(self.frames, emulator));
+                return;
+            }
+        };
+
+        let elapsed = sample.at.elapsed();
+        if elapsed < SAMPLE_TIME {
+            return;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        self.fps = (self.frames - sample.frames) as f64 / seconds;
+        self.instructions_per_second = emulator.instructions().saturating_sub(sample.instructions) as f64 / seconds;
+        self.speed = emulator.emulated_time().saturating_sub(sample.emulated_time) as f64 / 1_000_000.0 / seconds;
+        self.sample = Some(Sample::take(self.frames, emulator));
+    }
+
+    // Draws the overlay over whatever the canvas holds, `keypad` being the
+    // bitmask of the held keys
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, keypad: u16) -> crate::Result<()> {
+        self.messages.retain(|(_, shown)| shown.elapsed() < MESSAGE_TIME);
+        if !self.visible && self.messages.is_empty() {
+            return Ok(());
+        }
+
+        let (width, height) = canvas.output_size()?;
+        let mut painter = Painter::new(canvas, (height / HEIGHT_PER_PIXEL).max(1) as i32);
+
+        let columns = width as i32 / painter.scale;
+        let rows = height as i32 / painter.scale;
+
+        if self.visible {
+            let stats = [
+                format!("{:.0} FPS", self.fps),
+                format!("{} IPS", si(self.instructions_per_second)),
+                format!("SPEED {:.2}X", self.speed),
+            ];
+            painter.text_panel(PADDING, PADDING, &stats)?;
+            painter.keypad(columns - PADDING - keypad_size(), PADDING, keypad)?;
+        }
+
+        let lines: Vec<String> = self.messages.iter().map(|(text, _)| text.clone()).collect();
+        if !lines.is_empty() {
+            let top = rows - PADDING - panel_size(&lines).1;
+            painter.text_panel(PADDING, top, &lines)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Draws in font pixels, each `scale` canvas pixels wide, with alpha
+// blending. The canvas gets its colour and blend mode back once done.
+pub struct Painter<'a> {
+    canvas: &'a mut Canvas<Window>,
+    pub scale: i32,
+    previous_colour: Color,
+    previous_blend: BlendMode,
+}
+
+impl<'a> Painter<'a> {
+    pub fn new(canvas: &'a mut Canvas<Window>, scale: i32) -> Self {
+        let previous_colour = canvas.draw_color();
+        let previous_blend = canvas.blend_mode();
+        canvas.set_blend_mode(BlendMode::Blend);
+
+        Painter {
+            canvas,
+            scale,
+            previous_colour,
+            previous_blend,
+        }
+    }
+}
+
+impl Drop for Painter<'_> {
+    fn drop(&mut self) {
+        self.canvas.set_blend_mode(self.previous_blend);
+        self.canvas.set_draw_color(self.previous_colour);
+    }
+}
+
+impl Painter<'_> {
+    pub fn fill(&mut self, x: i32, y: i32, width: i32, height: i32, colour: Color) -> crate::Result<()> {
+        let scale = self.scale;
+        self.canvas.set_draw_color(colour);
+        self.canvas.fill_rect(Rect::new(x * scale, y * scale, (width * scale) as u32, (height * scale) as u32))?;
+        Ok(())
+    }
+
+    pub fn text(&mut self, x: i32, y: i32, text: &str, colour: Color) -> crate::Result<()> {
+        let scale = self.scale;
+        let rects: Vec<Rect> = font::text_pixels(text)
+            .map(|(column, row)| Rect::new((x + column as i32) * scale, (y + row as i32) * scale, scale as u32, scale as u32))
+            .collect();
+        self.canvas.set_draw_color(colour);
+        self.canvas.fill_rects(&rects)?;
+        Ok(())
+    }
+
+    // Lines of text on a dark panel, its top left corner at `x`, `y`
+    fn text_panel(&mut self, x: i32, y: i32, lines: &[String]) -> crate::Result<()> {
+        let (width, height) = panel_size(lines);
+        self.fill(x, y, width, height, PANEL)?;
+        for (row, line) in lines.iter().enumerate() {
+            self.text(x + PADDING, y + PADDING + (row * LINE_HEIGHT) as i32, line, TEXT)?;
+        }
+        Ok(())
+    }
+
+    fn keypad(&mut self, x: i32, y: i32, keypad: u16) -> crate::Result<()> {
+        let size = keypad_size();
+        self.fill(x, y, size, size, PANEL)?;
+
+        for (row, keys) in KEYPAD.iter().enumerate() {
+            for (column, key) in keys.iter().enumerate() {
+                let left = x + PADDING + (column * KEY_CELL) as i32;
+                let top = y + PADDING + (row * KEY_CELL) as i32;
+                let label = format!("{:X}", key);
+
+                if keypad & (1 << key) != 0 {
+                    self.fill(left, top, KEY_CELL as i32 - 1, KEY_CELL as i32 - 1, TEXT)?;
+                    self.text(left + 2, top + 2, &label, INVERTED_TEXT)?;
+                }
+                else {
+                    self.text(left + 2, top + 2, &label, TEXT)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn keypad_size() -> i32 {
+    (4 * KEY_CELL) as i32 - 1 + 2 * PADDING
+}
+
+fn panel_size(lines: &[String]) -> (i32, i32) {
+    let widest = lines.iter().map(|line| font::text_width(line)).max().unwrap_or(0);
+    let width = widest as i32 + 2 * PADDING;
+    let height = (lines.len() * LINE_HEIGHT) as i32 - (LINE_HEIGHT - GLYPH_HEIGHT) as i32 + 2 * PADDING;
+    (width, height)
+}
+
+// `value` with a K or M suffix, e.g. 1.25M
+fn si(value: f64) -> String {
+    if value >= 1_000_000.0 {
+        format!("{:.2}M", value / 1_000_000.0)
+    }
+    else if value >= 1_000.0 {
+        format!("{:.1}K", value / 1_000.0)
+    }
+    else {
+        format!("{:.0}", value)
+    }
+}
@@ -0,0 +1,153 @@
+// Colours used to present the CHIP-8 screen
+//
+// A palette holds the four colours an XO-CHIP screen needs, indexed by the
+// planes lit at a pixel: background, plane 1 (the CHIP-8 foreground),
+// plane 2, and both planes. It is selected by theme name or given as a
+// comma separated list of RRGGBB colours: "bg,fg" or "bg,fg,plane2,both".
+
+use std::fmt;
+use std::str::FromStr;
+
+pub type Rgb = [u8; 3];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    colours: [Rgb; 4],
+}
+
+// Built-in themes, the first one is the default
+pub const THEMES: [(&str, Palette); 5] = [
+    ("mono", Palette { colours: [[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55]] }),
+    ("amber", Palette { colours: [[0x14, 0x0C, 0x00], [0xFF, 0xB0, 0x00], [0xB3, 0x6B, 0x00], [0xFF, 0xD4, 0x80]] }),
+    ("green", Palette { colours: [[0x00, 0x14, 0x00], [0x33, 0xFF, 0x33], [0x1F, 0x9F, 0x1F], [0xAA, 0xFF, 0xAA]] }),
+    ("lcd", Palette { colours: [[0x9B, 0xBC, 0x0F], [0x0F, 0x38, 0x0F], [0x30, 0x62, 0x30], [0x8B, 0xAC, 0x0F]] }),
+    ("octo", Palette { colours: [[0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]] }),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PaletteError(String);
+impl std::error::Error for PaletteError { }
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid palette: {}", self.0)
+    }
+}
+
+impl Default for Palette {
+    // the original white on black
+    fn default() -> Self {
+        THEMES[0].1
+    }
+}
+
+impl FromStr for Palette {
+    type Err = PaletteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, palette)) = THEMES.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+            return Ok(*palette);
+        }
+
+        let colours = s
+            .split(',')
+            .map(parse_rgb)
+            .collect::<Result<Vec<Rgb>, PaletteError>>()?;
+
+        match colours[..] {
+            // two colours, planes 2 and both derived from the foreground
+            [background, foreground] => Ok(Palette {
+                colours: [background, foreground, mix(background, foreground), foreground],
+            }),
+            [background, foreground, plane2, both] => Ok(Palette { colours: [background, foreground, plane2, both] }),
+            _ => {
+                let themes: Vec<&str> = THEMES.iter().map(|(name, _)| *name).collect();
+                Err(PaletteError(format!(
+                    "'{}' is neither a theme ({}) nor a list of 2 or 4 RRGGBB colours",
+                    s,
+                    themes.join(", ")
+                )))
+            }
+        }
+    }
+}
+
+// The theme name, or the four colours of a custom palette
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(theme) = self.theme() {
+            return write!(f, "{}", theme);
+        }
+        let colours: Vec<String> = self
+            .colours
+            .iter()
+            .map(|[r, g, b]| format!("{:02x}{:02x}{:02x}", r, g, b))
+            .collect();
+        write!(f, "{}", colours.join(","))
+    }
+}
+
+impl Palette {
+    pub fn background(&self) -> Rgb {
+        self.colours[0]
+    }
+
+    pub fn foreground(&self) -> Rgb {
+        self.colours[1]
+    }
+
+    // Name of the theme of this palette, None for custom colours
+    pub fn theme(&self) -> Option<&'static str> {
+        THEMES.iter().find(|(_, palette)| palette == self).map(|(name, _)| *name)
+    }
+
+    // The theme after or before this palette, for cycling at runtime; custom
+    // colours are followed by the first theme
+    pub fn next(&self) -> Palette {
+        self.cycle(1)
+    }
+
+    pub fn previous(&self) -> Palette {
+        self.cycle(THEMES.len() - 1)
+    }
+
+    fn cycle(&self, step: usize) -> Palette {
+        match THEMES.iter().position(|(_, palette)| palette == self) {
+            Some(idx) => THEMES[(idx + step) % THEMES.len()].1,
+            None => THEMES[0].1,
+        }
+    }
+
+    // Colour of a pixel by the bitmask of the planes lit there
+    pub fn colour(&self, planes: usize) -> Rgb {
+        self.colours[planes & 0x3]
+    }
+
+    // Colour of a pixel lit at `intensity` (0 - off, 255 - fully on),
+    // blended from the background to the foreground
+    pub fn blend(&self, intensity: u8) -> Rgb {
+        blend(self.background(), self.foreground(), intensity)
+    }
+}
+
+fn blend(from: Rgb, to: Rgb, intensity: u8) -> Rgb {
+    let mix = |from: u8, to: u8| {
+        let weighted = from as u32 * (255 - intensity as u32) + to as u32 * intensity as u32;
+        ((weighted + 127) / 255) as u8
+    };
+    [mix(from[0], to[0]), mix(from[1], to[1]), mix(from[2], to[2])]
+}
+
+fn mix(a: Rgb, b: Rgb) -> Rgb {
+    blend(a, b, 0x80)
+}
+
+fn parse_rgb(hex: &str) -> Result<Rgb, PaletteError> {
+    let digits = hex.trim().trim_start_matches('#');
+    let value = u32::from_str_radix(digits, 16)
+        .ok()
+        .filter(|_| digits.len() == 6)
+        .ok_or_else(|| PaletteError(format!("'{}' is not a RRGGBB colour", hex)))?;
+
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
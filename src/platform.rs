@@ -0,0 +1,161 @@
+// Guessing the platform a program was written for
+//
+// The code reachable from #200 is followed through jumps, calls and skips,
+// looking for instructions that only SUPER-CHIP or XO-CHIP have. Data is not
+// followed, so sprites that happen to look like such instructions are not
+// taken for them. Code only reached through Bnnn jumps is missed.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+use crate::chip8::LOADING_POINT;
+use crate::quirks::QuirkPreset;
+
+// The largest program the original CHIP-8 and SUPER-CHIP can load
+const MAX_PROGRAM_SIZE: usize = 0x1000 - LOADING_POINT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    Schip,
+    XoChip,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Platform::Chip8 => write!(f, "CHIP-8"),
+            Platform::Schip => write!(f, "SUPER-CHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+impl Serialize for Platform {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Platform {
+    // The quirks to play a program of this platform with
+    pub fn quirks(&self) -> QuirkPreset {
+        match self {
+            Platform::Chip8 => QuirkPreset::Chip8,
+            Platform::Schip => QuirkPreset::Schip,
+            Platform::XoChip => QuirkPreset::XoChip,
+        }
+    }
+}
+
+pub struct Detection {
+    pub platform: Platform,
+    // why it is not CHIP-8: the address and bytes of the first instruction
+    // of the platform, None for a program too large for CHIP-8
+    pub evidence: Option<(usize, u16)>,
+}
+
+pub fn detect(program: &[u8]) -> Detection {
+    let mut detection = Detection { platform: Platform::Chip8, evidence: None };
+    if program.len() > MAX_PROGRAM_SIZE {
+        detection.platform = Platform::XoChip;
+    }
+
+    for addr in reachable(program).into_keys() {
+        let Some(instruction_bytes) = fetch(program, addr) else {
+            continue;
+        };
+        let platform = platform_of(instruction_bytes);
+        // a large program is XO-CHIP already, its first XO-CHIP instruction
+        // is still worth showing
+        let first_of_platform = platform == detection.platform && platform != Platform::Chip8 && detection.evidence.is_none();
+        if platform > detection.platform || first_of_platform {
+            detection = Detection { platform, evidence: Some((addr, instruction_bytes)) };
+        }
+    }
+
+    detection
+}
+
+// The addresses of the instructions reachable from the start, in order,
+// with the value of I when the instruction is first reached if an Annn on
+// the way set it
+pub fn reachable(program: &[u8]) -> BTreeMap<usize, Option<u16>> {
+    let mut visited = BTreeMap::new();
+    let mut pending = vec![(LOADING_POINT, None)];
+
+    while let Some((addr, i)) = pending.pop() {
+        let Some(instruction_bytes) = fetch(program, addr) else {
+            continue;
+        };
+        if visited.contains_key(&addr) {
+            continue;
+        }
+        visited.insert(addr, i);
+
+        let nnn = (instruction_bytes & 0x0FFF) as usize;
+        let i = match instruction_bytes {
+            0xA000..=0xAFFF => Some(nnn as u16),
+            // XO-CHIP's F000 nnnn loads the next word in I
+            0xF000 => fetch(program, addr + 2),
+            // I moves by a register, to a font character or by a quirk
+            _ if matches!(instruction_bytes & 0xF0FF, 0xF01E | 0xF029 | 0xF030 | 0xF055 | 0xF065) => None,
+            _ => i,
+        };
+        // XO-CHIP skips over the 4 bytes of F000 nnnn
+        let skip = if fetch(program, addr + 2) == Some(0xF000) { 6 } else { 4 };
+        match instruction_bytes {
+            // RET, exit and Bnnn leave for somewhere unknown
+            0x00EE | 0x00FD => {}
+            0xB000..=0xBFFF => {}
+            0x1000..=0x1FFF => pending.push((nnn, i)),
+            // the subroutine may change I before returning
+            0x2000..=0x2FFF => pending.extend([(nnn, i), (addr + 2, None)]),
+            0x3000..=0x4FFF => pending.extend([(addr + 2, i), (addr + skip, i)]),
+            _ if instruction_bytes & 0xF00F == 0x5000 || instruction_bytes & 0xF00F == 0x9000 => {
+                pending.extend([(addr + 2, i), (addr + skip, i)]);
+            }
+            _ if instruction_bytes & 0xF0FF == 0xE09E || instruction_bytes & 0xF0FF == 0xE0A1 => {
+                pending.extend([(addr + 2, i), (addr + skip, i)]);
+            }
+            0xF000 => pending.push((addr + 4, i)),
+            _ => pending.push((addr + 2, i)),
+        }
+    }
+
+    visited
+}
+
+// The platform an instruction first appeared on
+pub fn platform_of(instruction_bytes: u16) -> Platform {
+    let low = instruction_bytes & 0x00FF;
+    match instruction_bytes & 0xF000 {
+        0x0000 => match instruction_bytes {
+            0x00FB..=0x00FF => Platform::Schip,
+            _ if instruction_bytes & 0xFFF0 == 0x00C0 => Platform::Schip,
+            _ if instruction_bytes & 0xFFF0 == 0x00D0 => Platform::XoChip,
+            _ => Platform::Chip8,
+        },
+        0x5000 if matches!(instruction_bytes & 0x000F, 0x2 | 0x3) => Platform::XoChip,
+        0xD000 if instruction_bytes & 0x000F == 0 => Platform::Schip,
+        0xF000 => match low {
+            0x30 | 0x75 | 0x85 => Platform::Schip,
+            0x00 if instruction_bytes == 0xF000 => Platform::XoChip,
+            0x01 | 0x3A => Platform::XoChip,
+            0x02 if instruction_bytes == 0xF002 => Platform::XoChip,
+            _ => Platform::Chip8,
+        },
+        _ => Platform::Chip8,
+    }
+}
+
+// The instruction at `addr` of a program loaded at #200
+pub fn fetch(program: &[u8], addr: usize) -> Option<u16> {
+    let offset = addr.checked_sub(LOADING_POINT)?;
+    match program.get(offset..offset + 2)? {
+        &[high, low] => Some(u16::from_be_bytes([high, low])),
+        _ => None,
+    }
+}
@@ -0,0 +1,161 @@
+// Instruction profiler
+//
+// Counts how many times every address and every opcode is executed and how
+// much emulated time (the "overtime" returned by each instruction) they take,
+// plus the instructions executed per frame. The report is written on exit.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::chip8::{Chip8, FRAME_TIME};
+use crate::disasm::{disassemble, instruction_at};
+use crate::opcodes::Opcodes;
+use crate::tracer::Tracer;
+
+// How many entries the hot-spot tables show
+const HOTTEST_ADDRESSES: usize = 32;
+const HEAVIEST_FRAMES: usize = 16;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Stats {
+    count: u64,
+    cost: u64,
+}
+
+impl Stats {
+    fn add(&mut self, overtime: isize) {
+        self.count += 1;
+        self.cost += overtime.max(0) as u64;
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct FrameStats {
+    instructions: u32,
+    cost: isize,
+}
+
+pub struct Profiler {
+    output: PathBuf,
+
+    // per executed address, with the instruction last seen there
+    by_address: HashMap<u16, (u16, Stats)>,
+    by_opcode: HashMap<Opcodes, Stats>,
+    frames: Vec<FrameStats>,
+
+    // instruction fetched in `before_step`
+    current: Option<(u16, u16)>,
+}
+
+impl Profiler {
+    pub fn new(output: PathBuf) -> Self {
+        Profiler {
+            output,
+            by_address: HashMap::new(),
+            by_opcode: HashMap::new(),
+            frames: Vec::new(),
+            current: None,
+        }
+    }
+
+    pub fn write_report(&self) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.output)?);
+
+        let total = self.by_opcode.values().fold(Stats::default(), |sum, stats| Stats {
+            count: sum.count + stats.count,
+            cost: sum.cost + stats.cost,
+        });
+        let percent = |cost: u64| if total.cost == 0 { 0.0 } else { cost as f64 * 100.0 / total.cost as f64 };
+
+        writeln!(out, "CHIP-8 profile")?;
+        writeln!(out, "frames: {}  instructions: {}  cost: {} us", self.frames.len(), total.count, total.cost)?;
+
+        writeln!(out)?;
+        writeln!(out, "Hottest addresses (by cost)")?;
+        writeln!(out, "{:>5} {:>10} {:>12} {:>7}  instruction", "addr", "count", "cost", "cost%")?;
+        let mut addresses: Vec<_> = self.by_address.iter().collect();
+        addresses.sort_by(|a, b| b.1 .1.cost.cmp(&a.1 .1.cost).then(a.0.cmp(b.0)));
+        for (addr, (instruction_bytes, stats)) in addresses.iter().take(HOTTEST_ADDRESSES) {
+            writeln!(
+                out,
+                "  {:03X} {:>10} {:>12} {:>6.2}%  {:04X}  {}",
+                addr,
+                stats.count,
+                stats.cost,
+                percent(stats.cost),
+                instruction_bytes,
+                disassemble(*instruction_bytes)
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Opcodes (by cost)")?;
+        writeln!(out, "{:<26} {:>10} {:>12} {:>7} {:>8}", "opcode", "count", "cost", "cost%", "avg")?;
+        let mut opcodes: Vec<_> = self.by_opcode.iter().collect();
+        opcodes.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.cost));
+        for (opcode, stats) in opcodes {
+            writeln!(
+                out,
+                "{:<26} {:>10} {:>12} {:>6.2}% {:>8}",
+                opcode.to_string(),
+                stats.count,
+                stats.cost,
+                percent(stats.cost),
+                stats.cost / stats.count.max(1)
+            )?;
+        }
+
+        // every frame runs instructions until its time is spent, going over by
+        // part of the last one and starting the next one that much shorter,
+        // so no frame costs more than its budget. What tells the heavy frames
+        // apart is how many instructions they got through.
+        let mut heaviest: Vec<_> = self.frames.iter().enumerate().collect();
+        heaviest.sort_by(|a, b| b.1.instructions.cmp(&a.1.instructions).then(a.0.cmp(&b.0)));
+        writeln!(out)?;
+        writeln!(out, "Heaviest frames (by instructions, {} us each)", FRAME_TIME)?;
+        for (frame, stats) in heaviest.iter().take(HEAVIEST_FRAMES) {
+            writeln!(out, "  frame {:>8}: {:>6} instructions, {:>8} us", frame, stats.instructions, stats.cost)?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "Per-frame instruction counts")?;
+        writeln!(out, "frame,instructions,cost")?;
+        for (frame, stats) in self.frames.iter().enumerate() {
+            writeln!(out, "{},{},{}", frame, stats.instructions, stats.cost)?;
+        }
+
+        out.flush()
+    }
+}
+
+impl Tracer for Profiler {
+    fn frame_started(&mut self, _emulator: &Chip8) {
+        self.frames.push(FrameStats::default());
+    }
+
+    fn before_step(&mut self, emulator: &Chip8) {
+        let pc = emulator.pc();
+        self.current = instruction_at(emulator.memory(), pc as usize).map(|bytes| (pc, bytes));
+    }
+
+    fn after_step(&mut self, _emulator: &Chip8, overtime: isize) {
+        let Some((pc, instruction_bytes)) = self.current.take() else {
+            return;
+        };
+
+        let entry = self.by_address.entry(pc).or_default();
+        entry.0 = instruction_bytes;
+        entry.1.add(overtime);
+
+        if let Ok(opcode) = Opcodes::try_from(instruction_bytes) {
+            self.by_opcode.entry(opcode).or_default().add(overtime);
+        }
+
+        if let Some(frame) = self.frames.last_mut() {
+            frame.instructions += 1;
+            frame.cost += overtime;
+        }
+    }
+}
@@ -0,0 +1,145 @@
+// Behaviours that differ between CHIP-8 interpreters
+//
+// Programs were written for a given interpreter and some only work with its
+// behaviour. The presets pick the behaviours of one platform:
+//   classic  what this emulator always did
+//   chip8    the original COSMAC VIP interpreter
+//   schip    SUPER-CHIP 1.1 on the HP48
+//   xochip   XO-CHIP, as in Octo
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    // 8xy1, 8xy2 and 8xy3 clear VF
+    pub vf_reset: bool,
+    // Fx55 and Fx65 leave I pointing after the last register
+    pub memory_increment: bool,
+    // 8xy6 and 8xyE shift VX in place rather than VY into VX
+    pub shift_vx: bool,
+    // 8xyE takes VF from VX before the shift even when it shifts VY
+    pub shift_carry_vx: bool,
+    // Bnnn jumps to nnn + VX, x being the top digit of nnn, rather than V0
+    pub jump_vx: bool,
+    // sprites are cut at the edges of the screen rather than wrapped around
+    pub clipping: bool,
+    // Dxyn waits for the next frame, as the VIP waited for the display
+    pub display_wait: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuirkPreset {
+    #[default]
+    Classic,
+    Chip8,
+    Schip,
+    XoChip,
+}
+
+const PRESETS: [(&str, QuirkPreset); 4] = [
+    ("classic", QuirkPreset::Classic),
+    ("chip8", QuirkPreset::Chip8),
+    ("schip", QuirkPreset::Schip),
+    ("xochip", QuirkPreset::XoChip),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct QuirkPresetError(String);
+impl std::error::Error for QuirkPresetError { }
+
+impl fmt::Display for QuirkPresetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid quirk preset: '{}', expected classic, chip8, schip or xochip", self.0)
+    }
+}
+
+impl FromStr for QuirkPreset {
+    type Err = QuirkPresetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PRESETS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, preset)| *preset)
+            .ok_or_else(|| QuirkPresetError(s.to_string()))
+    }
+}
+
+impl fmt::Display for QuirkPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = PRESETS.iter().find(|(_, preset)| preset == self).unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+impl Quirks {
+    // The register Bnnn adds to nnn
+    pub fn jump_register(&self, instruction_bytes: u16) -> usize {
+        if self.jump_vx { ((instruction_bytes >> 8) & 0x000F) as usize } else { 0 }
+    }
+}
+
+impl QuirkPreset {
+    // The preset following this one, for cycling at runtime
+    pub fn next(&self) -> QuirkPreset {
+        let idx = PRESETS.iter().position(|(_, preset)| preset == self).unwrap();
+        PRESETS[(idx + 1) % PRESETS.len()].1
+    }
+
+    pub fn previous(&self) -> QuirkPreset {
+        let idx = PRESETS.iter().position(|(_, preset)| preset == self).unwrap();
+        PRESETS[(idx + PRESETS.len() - 1) % PRESETS.len()].1
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            QuirkPreset::Classic => Quirks {
+                shift_carry_vx: true,
+                ..Quirks::default()
+            },
+            QuirkPreset::Chip8 => Quirks {
+                vf_reset: true,
+                memory_increment: true,
+                clipping: true,
+                display_wait: true,
+                ..Quirks::default()
+            },
+            QuirkPreset::Schip => Quirks {
+                shift_vx: true,
+                jump_vx: true,
+                clipping: true,
+                ..Quirks::default()
+            },
+            QuirkPreset::XoChip => Quirks {
+                memory_increment: true,
+                ..Quirks::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    // VF and V0 after 8 0 1 E, V0 = #81 and V1 = #01
+    fn shift_left(preset: QuirkPreset) -> (u8, u8) {
+        let mut emulator = Chip8::default();
+        emulator.set_quirks(preset.quirks());
+        emulator.memory_mut()[0x200..0x202].copy_from_slice(&[0x80, 0x1E]);
+        emulator.registers_mut()[0] = 0x81;
+        emulator.registers_mut()[1] = 0x01;
+        emulator.step().unwrap();
+        (emulator.registers()[0xF], emulator.registers()[0])
+    }
+
+    #[test]
+    fn shift_left_carry() {
+        assert_eq!(shift_left(QuirkPreset::Classic), (1, 0x02));
+        assert_eq!(shift_left(QuirkPreset::Chip8), (0, 0x02));
+        assert_eq!(shift_left(QuirkPreset::Schip), (1, 0x02));
+        assert_eq!(shift_left(QuirkPreset::XoChip), (0, 0x02));
+    }
+}
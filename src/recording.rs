@@ -0,0 +1,266 @@
+// Recording gameplay
+//
+// Frames are captured after the phosphor fade, exactly as presented in the
+// window. They can go to an animated GIF, or to a raw RGB24 video stream
+// plus a raw PCM audio stream for external encoders such as ffmpeg. The
+// sound alone can also be saved as a WAV file.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::audio::{BuzzerSettings, SoundRenderer};
+use crate::chip8::{BuzzerEvent, SCREEN_HEIGTH, SCREEN_WIDTH};
+use crate::palette::Palette;
+use crate::render::Phosphor;
+
+const FRAMES_PER_SECOND: u64 = 60;
+
+// GIF delays are in centiseconds and viewers slow down anything below 2
+const MIN_GIF_DELAY: u64 = 2;
+
+pub const RAW_AUDIO_FREQ: u32 = 44100;
+
+// The largest pixel size, GIF sizes are 16 bit
+pub const MAX_SCALE: u32 = (u16::MAX as usize / SCREEN_WIDTH) as u32;
+
+pub struct GifRecorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    scale: usize,
+
+    // frame waiting for its delay to be known, with the frame number it
+    // was shown at
+    pending: Option<(Vec<u8>, u64)>,
+    // frames captured so far
+    frames: u64,
+    // time covered by the frames already written, in centiseconds
+    written: u64,
+}
+
+impl GifRecorder {
+    pub fn create(path: &Path, scale: u32, palette: &Palette) -> crate::Result<Self> {
+        let scale = scale.max(1) as usize;
+
+        // the pixel values are the phosphor intensities, so the colour table
+        // holds all the 256 blends between background and foreground
+        let colours: Vec<u8> = (0..=255u8).flat_map(|intensity| palette.blend(intensity)).collect();
+
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = gif::Encoder::new(
+            file,
+            (SCREEN_WIDTH * scale) as u16,
+            (SCREEN_HEIGTH * scale) as u16,
+            &colours,
+        )?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        Ok(GifRecorder { encoder, scale, pending: None, frames: 0, written: 0 })
+    }
+
+    pub fn capture(&mut self, phosphor: &Phosphor) -> crate::Result<()> {
+        let pixels = scale_up(phosphor.intensity(), self.scale, 1);
+
+        match self.pending.take() {
+            // unchanged picture, the pending frame just stays longer
+            Some((pending, shown_at)) if pending == pixels => self.pending = Some((pending, shown_at)),
+            Some((pending, shown_at)) => {
+                if centiseconds(self.frames) - self.written >= MIN_GIF_DELAY {
+                    self.write_frame(&pending, self.frames)?;
+                    self.pending = Some((pixels, self.frames));
+                } else {
+                    // too short to be shown on its own, replaced by the new one
+                    self.pending = Some((pixels, shown_at));
+                }
+            }
+            None => self.pending = Some((pixels, self.frames)),
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> crate::Result<()> {
+        if let Some((pending, _)) = self.pending.take() {
+            self.write_frame(&pending, self.frames.max(1))?;
+        }
+        Ok(())
+    }
+
+    // Writes a frame lasting until frame number `until`
+    fn write_frame(&mut self, pixels: &[u8], until: u64) -> crate::Result<()> {
+        let end = centiseconds(until).max(self.written + MIN_GIF_DELAY);
+
+        let mut frame = gif::Frame::from_indexed_pixels(
+            (SCREEN_WIDTH * self.scale) as u16,
+            (SCREEN_HEIGTH * self.scale) as u16,
+            pixels.to_vec(),
+            None,
+        );
+        frame.delay = (end - self.written) as u16;
+        self.encoder.write_frame(&frame)?;
+
+        self.written = end;
+        Ok(())
+    }
+}
+
+// Raw streams: NAME.rgb holds RGB24 frames at 60 fps, NAME.pcm signed 16 bit
+// little-endian mono samples at RAW_AUDIO_FREQ with the buzzer
+pub struct RawRecorder {
+    video: BufWriter<File>,
+    audio: BufWriter<File>,
+    scale: usize,
+    palette: Palette,
+    sound: SoundRenderer,
+    samples: Vec<f32>,
+}
+
+impl RawRecorder {
+    pub fn create(name: &Path, scale: u32, palette: &Palette) -> crate::Result<Self> {
+        Ok(RawRecorder {
+            video: BufWriter::new(File::create(name.with_extension("rgb"))?),
+            audio: BufWriter::new(File::create(name.with_extension("pcm"))?),
+            scale: scale.max(1) as usize,
+            palette: *palette,
+            sound: SoundRenderer::new(BuzzerSettings::default(), RAW_AUDIO_FREQ as i32),
+            samples: Vec::new(),
+        })
+    }
+
+    pub fn frame_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH * self.scale, SCREEN_HEIGTH * self.scale)
+    }
+
+    // The sound is rendered up to the emulated time `until` with the buzzer
+    // `events`, as it is heard with `settings`
+    pub fn capture(&mut self, phosphor: &Phosphor, events: &[BuzzerEvent], until: u64, settings: &BuzzerSettings) -> crate::Result<()> {
+        let mut rgb = vec![0u8; SCREEN_WIDTH * SCREEN_HEIGTH * 3];
+        phosphor.write_rgb(&self.palette, &mut rgb, SCREEN_WIDTH * 3);
+        self.video.write_all(&scale_up(&rgb, self.scale, 3))?;
+
+        self.samples.clear();
+        self.sound.buzzer.settings = *settings;
+        self.sound.render(events, until, 1.0, &mut self.samples);
+        for &sample in self.samples.iter() {
+            let value = (sample * i16::MAX as f32) as i16;
+            self.audio.write_all(&value.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> crate::Result<()> {
+        self.video.flush()?;
+        self.audio.flush()?;
+        Ok(())
+    }
+}
+
+// The buzzer in emulated time, as 16 bit mono samples at RAW_AUDIO_FREQ
+pub struct WavRecorder {
+    writer: hound::WavWriter<BufWriter<File>>,
+    sound: SoundRenderer,
+    samples: Vec<f32>,
+}
+
+impl WavRecorder {
+    pub fn create(path: &Path) -> crate::Result<Self> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: RAW_AUDIO_FREQ,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        Ok(WavRecorder {
+            writer: hound::WavWriter::create(path, spec)?,
+            sound: SoundRenderer::new(BuzzerSettings::default(), RAW_AUDIO_FREQ as i32),
+            samples: Vec::new(),
+        })
+    }
+
+    // Renders the sound up to the emulated time `until` with the buzzer
+    // `events`, as it is heard with `settings`
+    pub fn capture(&mut self, events: &[BuzzerEvent], until: u64, settings: &BuzzerSettings) -> crate::Result<()> {
+        self.samples.clear();
+        self.sound.buzzer.settings = *settings;
+        self.sound.render(events, until, 1.0, &mut self.samples);
+        for &sample in self.samples.iter() {
+            self.writer.write_sample((sample * i16::MAX as f32) as i16)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> crate::Result<()> {
+        self.writer.finalize()?;
+        Ok(())
+    }
+}
+
+fn centiseconds(frames: u64) -> u64 {
+    (frames * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND
+}
+
+// Repeats every pixel (of `channels` bytes) of a 64x32 picture into a
+// `scale` x `scale` block
+fn scale_up(pixels: &[u8], scale: usize, channels: usize) -> Vec<u8> {
+    let width = SCREEN_WIDTH * scale;
+    let mut scaled = Vec::with_capacity(width * SCREEN_HEIGTH * scale * channels);
+
+    for y in 0..SCREEN_HEIGTH * scale {
+        for x in 0..width {
+            let offset = ((y / scale) * SCREEN_WIDTH + x / scale) * channels;
+            scaled.extend_from_slice(&pixels[offset..offset + channels]);
+        }
+    }
+
+    scaled
+}
+
+// The recordings currently running
+#[derive(Default)]
+pub struct Recording {
+    pub gif: Option<GifRecorder>,
+    pub raw: Option<RawRecorder>,
+    pub wav: Option<WavRecorder>,
+}
+
+impl Recording {
+    // Adds the frame just presented to every running recording
+    pub fn capture(&mut self, phosphor: &Phosphor, events: &[BuzzerEvent], until: u64, buzzer: &BuzzerSettings) -> crate::Result<()> {
+        if let Some(gif) = self.gif.as_mut() {
+            gif.capture(phosphor)?;
+        }
+        if let Some(raw) = self.raw.as_mut() {
+            raw.capture(phosphor, events, until, buzzer)?;
+        }
+        if let Some(wav) = self.wav.as_mut() {
+            wav.capture(events, until, buzzer)?;
+        }
+        Ok(())
+    }
+
+    // Follows the machine jumping to the emulated time `time` with the
+    // buzzer `on`, the sound going on without a gap
+    pub fn resync(&mut self, time: u64, on: bool) {
+        if let Some(raw) = self.raw.as_mut() {
+            raw.sound.resync(time, on);
+        }
+        if let Some(wav) = self.wav.as_mut() {
+            wav.sound.resync(time, on);
+        }
+    }
+
+    pub fn finish(&mut self) -> crate::Result<()> {
+        if let Some(gif) = self.gif.take() {
+            gif.finish()?;
+        }
+        if let Some(raw) = self.raw.take() {
+            raw.finish()?;
+        }
+        if let Some(wav) = self.wav.take() {
+            wav.finish()?;
+        }
+        Ok(())
+    }
+}
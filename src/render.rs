@@ -0,0 +1,265 @@
+// Turning the CHIP-8 framebuffer into the picture presented to the user
+//
+// CHIP-8 games draw with XOR, so moving sprites are erased and drawn again
+// and flicker. `Persistence` selects how that is hidden:
+//   off          pixels are shown exactly as in the framebuffer
+//   fade:STEP    switched off pixels lose STEP (0-255) of brightness per frame
+//   decay:KEEP   switched off pixels keep KEEP (0.0-1.0) of their brightness per frame
+//   blend:N      every pixel is the average of the last N framebuffers
+//   settle:N     a new picture is presented once the framebuffer stayed the
+//                same for a frame, or at the latest after N frames
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use sdl2::rect::Rect;
+
+use crate::chip8::{SCREEN_HEIGTH, SCREEN_WIDTH};
+use crate::palette::Palette;
+
+const GFX_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGTH / 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Persistence {
+    Off,
+    Fade(u8),
+    Decay(f32),
+    Blend(usize),
+    Settle(usize),
+}
+
+impl Default for Persistence {
+    // the fade the emulator always had
+    fn default() -> Self {
+        Persistence::Fade(30)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PersistenceError(String);
+impl std::error::Error for PersistenceError { }
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid persistence mode: {}", self.0)
+    }
+}
+
+impl FromStr for Persistence {
+    type Err = PersistenceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, value) = s.split_once(':').unwrap_or((s, ""));
+        let invalid = || PersistenceError(format!("'{}', expected off, fade:STEP, decay:KEEP, blend:N or settle:N", s));
+
+        match mode {
+            "off" => Ok(Persistence::Off),
+            "fade" => value.parse().map(Persistence::Fade).map_err(|_| invalid()),
+            "decay" => value
+                .parse()
+                .ok()
+                .filter(|keep| (0.0..=1.0).contains(keep))
+                .map(Persistence::Decay)
+                .ok_or_else(invalid),
+            "blend" => value
+                .parse()
+                .ok()
+                .filter(|frames| *frames > 0)
+                .map(Persistence::Blend)
+                .ok_or_else(invalid),
+            "settle" => value
+                .parse()
+                .ok()
+                .filter(|frames| *frames > 0)
+                .map(Persistence::Settle)
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Persistence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Persistence::Off => write!(f, "off"),
+            Persistence::Fade(step) => write!(f, "fade:{}", step),
+            Persistence::Decay(keep) => write!(f, "decay:{}", keep),
+            Persistence::Blend(frames) => write!(f, "blend:{}", frames),
+            Persistence::Settle(frames) => write!(f, "settle:{}", frames),
+        }
+    }
+}
+
+// How the picture is fitted into the window
+//   integer  the largest whole multiple of the resolution that fits, so that
+//            all the pixels have the same size
+//   fit      as large as fits while keeping the aspect ratio
+// whatever is left of the window is a letterbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scaling {
+    #[default]
+    Integer,
+    Fit,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ScalingError(String);
+impl std::error::Error for ScalingError { }
+
+impl fmt::Display for ScalingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid scaling: '{}', expected integer or fit", self.0)
+    }
+}
+
+impl FromStr for Scaling {
+    type Err = ScalingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integer" => Ok(Scaling::Integer),
+            "fit" => Ok(Scaling::Fit),
+            _ => Err(ScalingError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Scaling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scaling::Integer => write!(f, "integer"),
+            Scaling::Fit => write!(f, "fit"),
+        }
+    }
+}
+
+impl Scaling {
+    // Where a picture of `resolution` goes in a window of `output` size,
+    // centred
+    pub fn viewport(&self, output: (u32, u32), resolution: (u32, u32)) -> Rect {
+        let (output_width, output_height) = output;
+        let (width, height) = resolution;
+
+        let (width, height) = match self {
+            Scaling::Integer => {
+                let scale = (output_width / width).min(output_height / height).max(1);
+                (width * scale, height * scale)
+            }
+            Scaling::Fit => {
+                // compare the aspect ratios without rounding
+                if output_width as u64 * height as u64 <= output_height as u64 * width as u64 {
+                    (output_width, output_width * height / width)
+                }
+                else {
+                    (output_height * width / height, output_height)
+                }
+            }
+        };
+
+        Rect::new(
+            (output_width as i32 - width as i32) / 2,
+            (output_height as i32 - height as i32) / 2,
+            width.max(1),
+            height.max(1),
+        )
+    }
+}
+
+pub struct Phosphor {
+    mode: Persistence,
+
+    // brightness of every pixel, 0 - off, 255 - fully on
+    intensity: [u8; SCREEN_WIDTH * SCREEN_HEIGTH],
+
+    // the last framebuffers seen, newest at the back (blend and settle)
+    history: VecDeque<[u8; GFX_SIZE]>,
+    // frames since the picture was last presented (settle)
+    held: usize,
+}
+
+impl Phosphor {
+    pub fn new(mode: Persistence) -> Self {
+        Phosphor {
+            mode,
+            intensity: [0; SCREEN_WIDTH * SCREEN_HEIGTH],
+            history: VecDeque::new(),
+            held: 0,
+        }
+    }
+
+    // Updates the picture with the framebuffer `gfx` (as returned by
+    // `Chip8::gfx`); called once per frame
+    pub fn update(&mut self, gfx: &[u8; GFX_SIZE]) {
+        match self.mode {
+            Persistence::Off => self.each_pixel(gfx, |on, _| if on { 255 } else { 0 }),
+            Persistence::Fade(step) => {
+                self.each_pixel(gfx, |on, pixel| if on { 255 } else { pixel.saturating_sub(step) })
+            }
+            Persistence::Decay(keep) => {
+                self.each_pixel(gfx, |on, pixel| if on { 255 } else { (pixel as f32 * keep) as u8 })
+            }
+            Persistence::Blend(frames) => {
+                self.remember(gfx, frames);
+
+                let mut lit = [0usize; SCREEN_WIDTH * SCREEN_HEIGTH];
+                for past in self.history.iter() {
+                    for (idx, count) in lit.iter_mut().enumerate() {
+                        *count += pixel_on(past, idx) as usize;
+                    }
+                }
+                for (pixel, count) in self.intensity.iter_mut().zip(lit) {
+                    *pixel = (count * 255 / self.history.len()) as u8;
+                }
+            }
+            Persistence::Settle(max_held) => {
+                let settled = self.history.back() == Some(gfx);
+                self.remember(gfx, 1);
+                self.held += 1;
+
+                if settled || self.held >= max_held {
+                    self.held = 0;
+                    self.each_pixel(gfx, |on, _| if on { 255 } else { 0 });
+                }
+            }
+        }
+    }
+
+    fn remember(&mut self, gfx: &[u8; GFX_SIZE], frames: usize) {
+        self.history.push_back(*gfx);
+        while self.history.len() > frames {
+            self.history.pop_front();
+        }
+    }
+
+    fn each_pixel(&mut self, gfx: &[u8; GFX_SIZE], update: impl Fn(bool, u8) -> u8) {
+        for (idx, pixel) in self.intensity.iter_mut().enumerate() {
+            *pixel = update(pixel_on(gfx, idx), *pixel);
+        }
+    }
+
+    // Size of the picture in CHIP-8 pixels
+    pub fn resolution(&self) -> (u32, u32) {
+        (SCREEN_WIDTH as u32, SCREEN_HEIGTH as u32)
+    }
+
+    pub fn intensity(&self) -> &[u8] {
+        &self.intensity
+    }
+
+    // Fills an RGB24 buffer of SCREEN_WIDTH x SCREEN_HEIGTH pixels
+    pub fn write_rgb(&self, palette: &Palette, buffer: &mut [u8], pitch: usize) {
+        for y in 0..SCREEN_HEIGTH {
+            for x in 0..SCREEN_WIDTH {
+                let offset = y * pitch + x * 3;
+                let rgb = palette.blend(self.intensity[y * SCREEN_WIDTH + x]);
+                buffer[offset..offset + 3].copy_from_slice(&rgb);
+            }
+        }
+    }
+}
+
+// Whether pixel number `idx` (row major) is set in the framebuffer
+fn pixel_on(gfx: &[u8; GFX_SIZE], idx: usize) -> bool {
+    gfx[idx / 8] & 1 << (7 - idx % 8) != 0
+}
@@ -0,0 +1,44 @@
+// Database of known CHIP-8 programs
+//
+// Programs are identified by the SHA-1 of their bytes, so a ROM is found
+// whatever its file is called. The database is built into the binary from
+// roms.json. An entry can carry the settings the program needs, in the
+// layout of the configuration file.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::config::ConfigLayer;
+
+const BUILTIN: &str = include_str!("roms.json");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RomInfo {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub config: ConfigLayer,
+}
+
+pub struct RomDatabase {
+    roms: HashMap<String, RomInfo>,
+}
+
+impl RomDatabase {
+    pub fn builtin() -> Self {
+        RomDatabase {
+            roms: serde_json::from_str(BUILTIN).expect("roms.json is not a valid ROM database"),
+        }
+    }
+
+    pub fn lookup(&self, program: &[u8]) -> Option<&RomInfo> {
+        self.roms.get(&rom_hash(program))
+    }
+}
+
+// Lowercase hex SHA-1 of the program bytes
+pub fn rom_hash(program: &[u8]) -> String {
+    sha1_smol::Sha1::from(program).digest().to_string()
+}
@@ -0,0 +1,65 @@
+// Saving the CHIP-8 screen as a PNG image, and as text for the console
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::chip8::{SCREEN_HEIGTH, SCREEN_WIDTH};
+use crate::palette::Palette;
+
+// Writes `gfx` (as returned by `Chip8::gfx`) to `path`, every CHIP-8 pixel
+// becoming a `scale` x `scale` block. A scale of 1 is the raw 64x32 screen.
+pub fn save_png(path: &Path, gfx: &[u8], scale: u32, palette: &Palette) -> crate::Result<()> {
+    let mut pixels = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGTH * 3);
+    for y in 0..SCREEN_HEIGTH {
+        for x in 0..SCREEN_WIDTH {
+            let on = gfx[y * SCREEN_WIDTH / 8 + x / 8] & 1 << (7 - x % 8) != 0;
+            pixels.extend_from_slice(&palette.colour(on as usize));
+        }
+    }
+
+    save_rgb_png(path, &pixels, (SCREEN_WIDTH, SCREEN_HEIGTH), scale)
+}
+
+// Writes an RGB24 image of `width` x `height` pixels to `path`, scaled up
+// the same way
+pub fn save_rgb_png(path: &Path, pixels: &[u8], (width, height): (usize, usize), scale: u32) -> crate::Result<()> {
+    let scale = scale.max(1) as usize;
+
+    let mut scaled = Vec::with_capacity(width * height * scale * scale * 3);
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            let offset = ((y / scale) * width + x / scale) * 3;
+            scaled.extend_from_slice(&pixels[offset..offset + 3]);
+        }
+    }
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&scaled)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+// Lowercase hex SHA-1 of `gfx`, to compare screens
+pub fn hash(gfx: &[u8]) -> String {
+    sha1_smol::Sha1::from(gfx).digest().to_string()
+}
+
+// `gfx` as 32 lines of 64 characters, # for lit pixels and . for the others
+pub fn text(gfx: &[u8]) -> String {
+    let mut text = String::with_capacity((SCREEN_WIDTH + 1) * SCREEN_HEIGTH);
+    for y in 0..SCREEN_HEIGTH {
+        for x in 0..SCREEN_WIDTH {
+            let on = gfx[y * SCREEN_WIDTH / 8 + x / 8] & 1 << (7 - x % 8) != 0;
+            text.push(if on { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+    text
+}
@@ -0,0 +1,314 @@
+// Scripts driving the emulator, written in Rhai (https://rhai.rs)
+//
+// `--script FILE` runs the script once when the program starts, before the
+// first frame, then the hooks it set up while the game runs, in the window or
+// `headless`. The script sees the machine through these functions (numbers
+// are addresses, registers and keys, `0x2F0` in hex):
+//
+//   pc() i() v(x) dt() st()             the registers
+//   set_pc(a) set_i(a) set_v(x, n) set_dt(n) set_st(n)
+//   peek(a) poke(a, n)                  a byte of memory
+//   press(k) release(k) release()       hold a key down, as well as the player
+//   frame() frames(n)                   run the rest of the frame, or n frames
+//   step()                              run a single instruction
+//   frame_count()                       the frames run since the start
+//   pixel(x, y) screen() screen_hash()  the 64x32 screen, as text or a SHA-1
+//   screenshot(path) screenshot(path, scale)
+//   on_pc(a, || ...)                    called before the instruction at `a`
+//   on_frame(|| ...)                    called after every frame
+//
+// A test plays a sequence and throws when the game is not where expected:
+//
+//   frames(100);
+//   press(0x5); frames(4); release();
+//   frames(60);
+//   if screen_hash() != "..." { throw "wrong screen after the menu" }
+//
+// run with `chip-8 headless GAME --script test.rhai -n 0`. The frames run by
+// the script itself are not seen by `--profile`, `--coverage` and the other
+// tools, only the ones of the window or of `-n`.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, NativeCallContext, AST, INT};
+
+use crate::chip8::{Chip8, MEMORY_SIZE, REGISTERS_COUNT, SCREEN_HEIGTH, SCREEN_WIDTH};
+use crate::palette::Palette;
+use crate::screenshot;
+use crate::tracer::Tracer;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// What the functions of the script act on
+#[derive(Default)]
+struct Machine {
+    // the machine of the game, lent to the script while it runs
+    emulator: Chip8,
+    // the keys the script holds down
+    keys: u16,
+    frames: u64,
+    hooks: BTreeMap<u16, FnPtr>,
+    on_frame: Option<FnPtr>,
+    palette: Palette,
+}
+
+type Shared = Rc<RefCell<Machine>>;
+
+pub struct Script {
+    path: PathBuf,
+    engine: Engine,
+    ast: AST,
+    machine: Shared,
+}
+
+impl Script {
+    // Compiles the script and runs it on `emulator`
+    pub fn load(path: &Path, emulator: &mut Chip8, palette: Palette) -> crate::Result<Self> {
+        let machine = Rc::new(RefCell::new(Machine { palette, ..Machine::default() }));
+        let engine = engine(&machine);
+        let ast = engine.compile_file(path.to_path_buf()).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        let script = Script { path: path.to_path_buf(), engine, ast, machine };
+        script.lend(emulator, |script| script.engine.run_ast(&script.ast))?;
+        Ok(script)
+    }
+
+    // Runs the rest of the frame, or a new one, with the hooks of the script.
+    // `key` is held down with the keys of the script.
+    pub fn run_frame(&mut self, emulator: &mut Chip8, key: u16, tracer: &mut dyn Tracer) -> crate::Result<()> {
+        self.lend(emulator, |script| {
+            run_frame(&script.machine, key, tracer, &mut |hook| hook.call::<Dynamic>(&script.engine, &script.ast, ()).map(drop))
+        })
+    }
+
+    // The frames run, by the script and by `run_frame`
+    pub fn frames(&self) -> u64 {
+        self.machine.borrow().frames
+    }
+
+    // Lends `emulator` to the script for `run`
+    fn lend(&self, emulator: &mut Chip8, run: impl FnOnce(&Self) -> ScriptResult<()>) -> crate::Result<()> {
+        std::mem::swap(emulator, &mut self.machine.borrow_mut().emulator);
+        let result = run(self);
+        std::mem::swap(emulator, &mut self.machine.borrow_mut().emulator);
+
+        result.map_err(|err| format!("{}: {}", self.path.display(), err).into())
+    }
+}
+
+// Runs the rest of the frame, or a new one, stopping at the hooked addresses
+// to `call` the hooks, then calls `on_frame`
+fn run_frame(
+    machine: &Shared,
+    key: u16,
+    tracer: &mut dyn Tracer,
+    call: &mut dyn FnMut(&FnPtr) -> ScriptResult<()>,
+) -> ScriptResult<()> {
+    {
+        let mut machine = machine.borrow_mut();
+        if !machine.emulator.frame_pending() {
+            let keys = key | machine.keys;
+            machine.emulator.begin_frame(keys);
+            machine.frames += 1;
+            tracer.frame_started(&machine.emulator);
+        }
+    }
+
+    // the hook just called, its instruction is executed next
+    let mut called = None;
+    loop {
+        // the machine is not borrowed while a hook runs, as it uses it
+        let hook = {
+            let mut machine = machine.borrow_mut();
+            let machine = &mut *machine;
+            loop {
+                if !machine.emulator.frame_pending() {
+                    break None;
+                }
+                let pc = machine.emulator.pc();
+                if called != Some(pc) {
+                    if let Some(hook) = machine.hooks.get(&pc) {
+                        break Some((pc, hook.clone()));
+                    }
+                }
+                called = None;
+                machine.emulator.step_traced(tracer).map_err(|err| err.to_string())?;
+            }
+        };
+
+        match hook {
+            Some((pc, hook)) => {
+                called = Some(pc);
+                call(&hook)?;
+            }
+            None => break,
+        }
+    }
+
+    let on_frame = machine.borrow().on_frame.clone();
+    if let Some(on_frame) = on_frame {
+        call(&on_frame)?;
+    }
+    Ok(())
+}
+
+fn engine(machine: &Shared) -> Engine {
+    let mut engine = Engine::new();
+
+    // Registers
+    let shared = machine.clone();
+    engine.register_fn("pc", move || shared.borrow().emulator.pc() as INT);
+    let shared = machine.clone();
+    engine.register_fn("set_pc", move |addr: INT| -> ScriptResult<()> {
+        shared.borrow_mut().emulator.set_pc(address(addr)?);
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("i", move || shared.borrow().emulator.i() as INT);
+    let shared = machine.clone();
+    engine.register_fn("set_i", move |addr: INT| -> ScriptResult<()> {
+        shared.borrow_mut().emulator.set_i(address(addr)?);
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("v", move |x: INT| -> ScriptResult<INT> {
+        Ok(shared.borrow().emulator.registers()[register(x)?] as INT)
+    });
+    let shared = machine.clone();
+    engine.register_fn("set_v", move |x: INT, value: INT| -> ScriptResult<()> {
+        shared.borrow_mut().emulator.registers_mut()[register(x)?] = byte(value)?;
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("dt", move || shared.borrow().emulator.delay_timer() as INT);
+    let shared = machine.clone();
+    engine.register_fn("set_dt", move |value: INT| -> ScriptResult<()> {
+        shared.borrow_mut().emulator.set_delay_timer(byte(value)?);
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("st", move || shared.borrow().emulator.sound_timer() as INT);
+    let shared = machine.clone();
+    engine.register_fn("set_st", move |value: INT| -> ScriptResult<()> {
+        shared.borrow_mut().emulator.set_sound_timer(byte(value)?);
+        Ok(())
+    });
+
+    // Memory
+    let shared = machine.clone();
+    engine.register_fn("peek", move |addr: INT| -> ScriptResult<INT> {
+        Ok(shared.borrow().emulator.memory()[address(addr)? as usize] as INT)
+    });
+    let shared = machine.clone();
+    engine.register_fn("poke", move |addr: INT, value: INT| -> ScriptResult<()> {
+        shared.borrow_mut().emulator.memory_mut()[address(addr)? as usize] = byte(value)?;
+        Ok(())
+    });
+
+    // Keys
+    let shared = machine.clone();
+    engine.register_fn("press", move |k: INT| -> ScriptResult<()> {
+        shared.borrow_mut().keys |= 1 << key(k)?;
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("release", move |k: INT| -> ScriptResult<()> {
+        shared.borrow_mut().keys &= !(1 << key(k)?);
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("release", move || shared.borrow_mut().keys = 0);
+
+    // Running
+    let shared = machine.clone();
+    engine.register_fn("frame", move |context: NativeCallContext| -> ScriptResult<()> {
+        run_frame(&shared, 0, &mut (), &mut |hook| hook.call_within_context::<Dynamic>(&context, ()).map(drop))
+    });
+    let shared = machine.clone();
+    engine.register_fn("frames", move |context: NativeCallContext, n: INT| -> ScriptResult<()> {
+        for _ in 0..n {
+            run_frame(&shared, 0, &mut (), &mut |hook| hook.call_within_context::<Dynamic>(&context, ()).map(drop))?;
+        }
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("step", move || -> ScriptResult<()> {
+        let mut machine = shared.borrow_mut();
+        if !machine.emulator.frame_pending() {
+            let keys = machine.keys;
+            machine.emulator.begin_frame(keys);
+            machine.frames += 1;
+        }
+        machine.emulator.step().map_err(|err| err.to_string())?;
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("frame_count", move || shared.borrow().frames as INT);
+
+    // Screen
+    let shared = machine.clone();
+    engine.register_fn("pixel", move |x: INT, y: INT| -> ScriptResult<bool> {
+        if !(0..SCREEN_WIDTH as INT).contains(&x) || !(0..SCREEN_HEIGTH as INT).contains(&y) {
+            return Err(format!("({}, {}) is out of the screen", x, y).into());
+        }
+        let (x, y) = (x as usize, y as usize);
+        Ok(shared.borrow().emulator.gfx()[y * SCREEN_WIDTH / 8 + x / 8] & 1 << (7 - x % 8) != 0)
+    });
+    let shared = machine.clone();
+    engine.register_fn("screen", move || screenshot::text(&shared.borrow().emulator.gfx()));
+    let shared = machine.clone();
+    engine.register_fn("screen_hash", move || screenshot::hash(&shared.borrow().emulator.gfx()));
+    let shared = machine.clone();
+    engine.register_fn("screenshot", move |path: &str| save_screenshot(&shared, path, 1));
+    let shared = machine.clone();
+    engine.register_fn("screenshot", move |path: &str, scale: INT| save_screenshot(&shared, path, scale));
+
+    // Hooks
+    let shared = machine.clone();
+    engine.register_fn("on_pc", move |addr: INT, hook: FnPtr| -> ScriptResult<()> {
+        shared.borrow_mut().hooks.insert(address(addr)?, hook);
+        Ok(())
+    });
+    let shared = machine.clone();
+    engine.register_fn("on_frame", move |hook: FnPtr| shared.borrow_mut().on_frame = Some(hook));
+
+    engine
+}
+
+fn save_screenshot(machine: &Shared, path: &str, scale: INT) -> ScriptResult<()> {
+    let machine = machine.borrow();
+    let scale = u32::try_from(scale).map_err(|_| format!("invalid scale {}", scale))?;
+    screenshot::save_png(Path::new(path), &machine.emulator.gfx(), scale, &machine.palette)
+        .map_err(|err| format!("Cannot save {}: {}", path, err).into())
+}
+
+fn address(addr: INT) -> ScriptResult<u16> {
+    if (0..MEMORY_SIZE as INT).contains(&addr) {
+        Ok(addr as u16)
+    } else {
+        Err(format!("#{:X} is out of memory", addr).into())
+    }
+}
+
+fn register(x: INT) -> ScriptResult<usize> {
+    if (0..REGISTERS_COUNT as INT).contains(&x) {
+        Ok(x as usize)
+    } else {
+        Err(format!("there is no register V{}", x).into())
+    }
+}
+
+fn key(k: INT) -> ScriptResult<u16> {
+    if (0..16).contains(&k) {
+        Ok(k as u16)
+    } else {
+        Err(format!("there is no key {}", k).into())
+    }
+}
+
+fn byte(value: INT) -> ScriptResult<u8> {
+    u8::try_from(value).map_err(|_| format!("{} is not a byte", value).into())
+}
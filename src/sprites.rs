@@ -0,0 +1,158 @@
+// Memory shown as 8 pixel wide sprites, to find the graphics of a program
+//
+// Memory is cut into sprites of `height` bytes, one byte per line as Dxyn
+// reads them, laid out SHEET_COLUMNS sprites to a row from left to right.
+// The built-in font is drawn in amber and the `height` bytes I points to in
+// blue, so the sprite a Dxyn is about to draw stands out.
+//
+// `--sprites` shows a page of them in a second window, where Up/Down scroll
+// by a row, PgUp/PgDn by a page, Left/Right change the height, Home goes
+// back to #000 and I to the sprite I points to. The `sprites` command saves
+// a whole range as a PNG instead.
+
+use std::ops::Range;
+
+use sdl2::keyboard::Keycode;
+
+use crate::chip8::{MEMORY_SIZE, SPRITE_CHARS, SPRITE_CHARS_ADDR};
+
+pub const SHEET_COLUMNS: usize = 16;
+// a sprite and the gap after it
+const CELL_WIDTH: usize = 8 + 1;
+pub const SHEET_WIDTH: usize = SHEET_COLUMNS * CELL_WIDTH;
+// the page of the window, rows of sprites as fit
+pub const PAGE_HEIGHT: usize = 16 * CELL_WIDTH;
+// Dxy0 draws 16 lines on SUPER-CHIP
+pub const MAX_SPRITE_HEIGHT: usize = 16;
+
+// [off, on] colours
+const PLAIN: [[u8; 3]; 2] = [[24, 24, 24], [220, 220, 220]];
+const FONT: [[u8; 3]; 2] = [[40, 30, 12], [240, 180, 50]];
+const POINTED: [[u8; 3]; 2] = [[16, 36, 70], [90, 190, 255]];
+const GAP: [u8; 3] = [0, 0, 0];
+
+#[derive(Debug, Clone, Copy)]
+pub struct SpriteSheet {
+    // the address of the first sprite
+    pub start: usize,
+    // bytes per sprite
+    pub height: usize,
+}
+
+impl SpriteSheet {
+    pub fn new(start: usize, height: usize) -> Self {
+        SpriteSheet { start: start.min(MEMORY_SIZE), height: height.clamp(1, MAX_SPRITE_HEIGHT) }
+    }
+
+    fn row_bytes(&self) -> usize {
+        SHEET_COLUMNS * self.height
+    }
+
+    fn row_height(&self) -> usize {
+        self.height + 1
+    }
+
+    // The rows it takes to show memory up to `end`
+    pub fn rows(&self, end: usize) -> usize {
+        end.saturating_sub(self.start).div_ceil(self.row_bytes())
+    }
+
+    // The size in pixels of `rows` rows
+    pub fn size(&self, rows: usize) -> (usize, usize) {
+        (SHEET_WIDTH, rows * self.row_height())
+    }
+
+    // Fills an RGB24 buffer of `self.size(rows)` pixels or more with the
+    // sprites from `start`, up to `end`. `i` is highlighted.
+    pub fn render(&self, memory: &[u8], end: usize, i: usize, rows: usize, buffer: &mut [u8], pitch: usize) {
+        let end = end.min(memory.len());
+        let font = SPRITE_CHARS_ADDR as usize..SPRITE_CHARS_ADDR as usize + SPRITE_CHARS.len() * SPRITE_CHARS[0].len();
+        let pointed = i..i + self.height;
+        let (width, height) = self.size(rows);
+
+        for y in 0..height {
+            let line = y % self.row_height();
+            for x in 0..width {
+                let column = x / CELL_WIDTH;
+                let bit = x % CELL_WIDTH;
+                let addr = self.start + (y / self.row_height()) * self.row_bytes() + column * self.height + line;
+
+                let rgb = if bit == 8 || line == self.height || addr >= end {
+                    GAP
+                } else {
+                    let colours = colours(addr, &font, &pointed);
+                    colours[(memory[addr] >> (7 - bit) & 1) as usize]
+                };
+                let offset = y * pitch + x * 3;
+                buffer[offset..offset + 3].copy_from_slice(&rgb);
+            }
+        }
+    }
+}
+
+fn colours(addr: usize, font: &Range<usize>, pointed: &Range<usize>) -> [[u8; 3]; 2] {
+    if pointed.contains(&addr) {
+        POINTED
+    } else if font.contains(&addr) {
+        FONT
+    } else {
+        PLAIN
+    }
+}
+
+// The sheet of the `--sprites` window, a page at a time
+pub struct SpriteViewer {
+    pub sheet: SpriteSheet,
+}
+
+impl Default for SpriteViewer {
+    fn default() -> Self {
+        SpriteViewer { sheet: SpriteSheet::new(0, 8) }
+    }
+}
+
+impl SpriteViewer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rows(&self) -> usize {
+        PAGE_HEIGHT / self.sheet.row_height()
+    }
+
+    // Fills an RGB24 buffer of SHEET_WIDTH x PAGE_HEIGHT pixels
+    pub fn render(&self, memory: &[u8], i: usize, buffer: &mut [u8], pitch: usize) {
+        // below the last row that fits
+        buffer.fill(0);
+        self.sheet.render(memory, MEMORY_SIZE, i, self.rows(), buffer, pitch);
+    }
+
+    // The window title, with the range shown
+    pub fn title(&self) -> String {
+        let end = (self.sheet.start + self.rows() * self.sheet.row_bytes()).min(MEMORY_SIZE);
+        format!("chip-8 sprites - {:03X}-{:03X}, {} lines", self.sheet.start, end - 1, self.sheet.height)
+    }
+
+    // Handles a key pressed in the window
+    pub fn key(&mut self, keycode: Keycode, i: usize) {
+        let row = self.sheet.row_bytes() as isize;
+        let page = row * self.rows() as isize;
+        match keycode {
+            Keycode::Up => self.scroll(-row),
+            Keycode::Down => self.scroll(row),
+            Keycode::PageUp => self.scroll(-page),
+            Keycode::PageDown => self.scroll(page),
+            Keycode::Left => self.sheet = SpriteSheet::new(self.sheet.start, self.sheet.height - 1),
+            Keycode::Right => self.sheet = SpriteSheet::new(self.sheet.start, self.sheet.height + 1),
+            Keycode::Home => self.sheet.start = 0,
+            // the row I is in, as the sprites are laid out from #000
+            Keycode::I => self.sheet.start = (i - i % self.sheet.row_bytes()).min(MEMORY_SIZE - 1),
+            _ => {}
+        }
+    }
+
+    fn scroll(&mut self, bytes: isize) {
+        let last = MEMORY_SIZE.saturating_sub(1) as isize;
+        self.sheet.start = (self.sheet.start as isize + bytes).clamp(0, last) as usize;
+    }
+}
@@ -25,6 +25,15 @@ impl<const COUNT: usize> Stack<COUNT> {
         Stack{data: [0; COUNT], top: -1}
     }
 
+    // A stack holding `values`, the last one on top
+    pub fn from_slice(values: &[u16]) -> Result<Self, StackError> {
+        let mut stack = Self::new();
+        for value in values {
+            stack.push(*value)?;
+        }
+        Ok(stack)
+    }
+
     pub fn top(&self) -> Option<u16> {
         if self.top == -1 {
             None
@@ -45,6 +54,11 @@ impl<const COUNT: usize> Stack<COUNT> {
         }
     }
 
+    // The pushed values, from the bottom of the stack to the top
+    pub fn as_slice(&self) -> &[u16] {
+        &self.data[..(self.top + 1) as usize]
+    }
+
     pub fn pop(&mut self) -> Result<(), StackError> {
         if self.top == -1 {
             Err(StackError("Stack is empty!"))
@@ -0,0 +1,145 @@
+// ROM test suites, run by the `test` command
+//
+// A suite is a TOML file listing programs to run without a window and what
+// their screen should look like at the end:
+//
+//   [[test]]
+//   name = "IBM logo"
+//   rom = "../programs/2-ibm-logo.ch8"   # relative to the suite
+//   frames = 60
+//   screen = "5a0c…"                     # SHA-1 of the screen, as `headless` prints it
+//   press = [{ key = "1", at = 10 }]      # keys pressed on the way, for 4 frames
+//   config = { emulation = { quirks = "chip8" } }
+//
+// The settings are the defaults, those of the ROM database and the `config`
+// of the test, never the user configuration file, so that a suite gives the
+// same results everywhere. A test fails when the screen differs or the
+// program stops on an error.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::config::{ConfigLayer, ConfigLayers};
+use crate::romdb::RomDatabase;
+use crate::{power_on, rom_info, screenshot, Result};
+
+const PRESS_FRAMES: u64 = 4;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Suite {
+    #[serde(rename = "test")]
+    tests: Vec<TestCase>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TestCase {
+    name: String,
+    rom: PathBuf,
+    frames: u64,
+    screen: String,
+    #[serde(default)]
+    press: Vec<Press>,
+    #[serde(default)]
+    config: ConfigLayer,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Press {
+    // the CHIP-8 key, a hex digit
+    key: String,
+    // the first frame, counting from 1, the key is held down at
+    at: u64,
+    #[serde(default = "press_frames")]
+    frames: u64,
+}
+
+fn press_frames() -> u64 {
+    PRESS_FRAMES
+}
+
+// How a test went, but for success
+enum Failure {
+    Screen(String),
+    Error(crate::Error),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Screen(hash) => write!(f, "the screen is {}", hash),
+            Failure::Error(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// Runs the tests of the suite at `path` whose name contains `filter`,
+// failing if any of them does
+pub fn run_suite(path: &Path, filter: Option<&str>) -> Result<()> {
+    let text = fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+    let suite: Suite = toml::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let database = RomDatabase::builtin();
+
+    let tests: Vec<&TestCase> = suite
+        .tests
+        .iter()
+        .filter(|test| filter.is_none_or(|filter| test.name.contains(filter)))
+        .collect();
+    println!("running {} tests", tests.len());
+
+    let mut failed = 0;
+    for test in &tests {
+        match run_test(test, directory, &database) {
+            Ok(()) => println!("test {} ... ok", test.name),
+            Err(failure) => {
+                println!("test {} ... FAILED: {}", test.name, failure);
+                failed += 1;
+            }
+        }
+    }
+
+    println!();
+    println!("{} passed, {} failed", tests.len() - failed, failed);
+    if failed > 0 {
+        return Err(format!("{} of {} tests failed", failed, tests.len()).into());
+    }
+    Ok(())
+}
+
+fn run_test(test: &TestCase, directory: &Path, database: &RomDatabase) -> std::result::Result<(), Failure> {
+    let rom = directory.join(&test.rom);
+    let config = ConfigLayers::without_user_file(&format!("test {}", test.name), test.config.clone())
+        .resolve(rom_info(&rom, database))
+        .map_err(Failure::Error)?;
+    let mut emulator = power_on(&rom, &config.emulation)
+        .map_err(|err| Failure::Error(format!("{}: {}", rom.display(), err).into()))?;
+
+    let presses = test
+        .press
+        .iter()
+        .map(|press| match u8::from_str_radix(&press.key, 16) {
+            Ok(key) if press.key.len() == 1 => Ok((key, press.at..press.at + press.frames)),
+            _ => Err(Failure::Error(format!("'{}' is not a CHIP-8 key, expected 0 to F", press.key).into())),
+        })
+        .collect::<std::result::Result<Vec<_>, Failure>>()?;
+
+    for frame in 1..=test.frames {
+        let keys = presses
+            .iter()
+            .filter(|(_, frames)| frames.contains(&frame))
+            .fold(0u16, |keys, (key, _)| keys | 1 << key);
+        emulator.cycle(keys).map_err(Failure::Error)?;
+    }
+
+    let screen = screenshot::hash(&emulator.gfx());
+    if !screen.eq_ignore_ascii_case(&test.screen) {
+        return Err(Failure::Screen(screen));
+    }
+    Ok(())
+}
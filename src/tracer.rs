@@ -0,0 +1,42 @@
+// Observers of the instructions executed by the emulator core
+//
+// Tools such as the profiler implement `Tracer` and are handed to
+// `Chip8::cycle_traced`/`Chip8::step_traced`, which call them around every
+// instruction. All the hooks default to doing nothing.
+
+use crate::chip8::Chip8;
+
+pub trait Tracer {
+    // A new 60Hz frame started (keys stored, timers updated)
+    fn frame_started(&mut self, _emulator: &Chip8) {}
+
+    // The instruction at `emulator.pc()` is about to be executed
+    fn before_step(&mut self, _emulator: &Chip8) {}
+
+    // The instruction finished, costing `overtime` microseconds
+    fn after_step(&mut self, _emulator: &Chip8, _overtime: isize) {}
+}
+
+// Tracing disabled
+impl Tracer for () {}
+
+// Several tracers at once, called in order
+impl Tracer for Vec<&mut dyn Tracer> {
+    fn frame_started(&mut self, emulator: &Chip8) {
+        for tracer in self.iter_mut() {
+            tracer.frame_started(emulator);
+        }
+    }
+
+    fn before_step(&mut self, emulator: &Chip8) {
+        for tracer in self.iter_mut() {
+            tracer.before_step(emulator);
+        }
+    }
+
+    fn after_step(&mut self, emulator: &Chip8, overtime: isize) {
+        for tracer in self.iter_mut() {
+            tracer.after_step(emulator, overtime);
+        }
+    }
+}
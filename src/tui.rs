@@ -0,0 +1,536 @@
+// Terminal UI debugger
+//
+// Full-screen view in the launching terminal, next to the SDL game window.
+// Shows the disassembly around `pc`, a hex dump of `memory`, the registers,
+// the stack, the timers and the keypad, and takes single key commands
+// (plus a `:` prompt for editing registers, poking memory and cheats).
+
+use std::collections::HashSet;
+use std::io::{self, Stdout};
+use std::time::Duration;
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use crate::cheats::{Cheat, CheatError, Cheats, Comparison, Target};
+use crate::chip8::{Chip8, MEMORY_SIZE, REGISTERS_COUNT};
+use crate::disasm::disassemble_at;
+use crate::tracer::Tracer;
+
+const HELP: &str = "space run/pause  s step  f frame  g run to cursor  b breakpoint  \
+                    ↑↓ cursor  PgUp/PgDn memory  : command  q quit";
+
+// Layout of the hex keypad as printed on the COSMAC VIP
+const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+const MEMORY_ROW: usize = 16;
+
+pub struct TuiDebugger {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    state: DebuggerState,
+}
+
+struct DebuggerState {
+    running: bool,
+    quit: bool,
+
+    breakpoints: HashSet<u16>,
+    // temporary breakpoint set by "run to cursor"
+    run_to: Option<u16>,
+
+    // selected line in the disassembly pane
+    cursor: u16,
+    // start of the hex dump, or None to follow the I register
+    memory_view: Option<u16>,
+
+    // text typed after ':' while the command prompt is open
+    prompt: Option<String>,
+    message: String,
+}
+
+impl TuiDebugger {
+    // Takes over the terminal. The machine starts paused at its first
+    // instruction.
+    pub fn new(emulator: &Chip8) -> io::Result<Self> {
+        // a panic would leave the terminal raw on the alternate screen, with
+        // its message lost in there
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore_terminal();
+            default_hook(info);
+        }));
+
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        let terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+
+        let state = DebuggerState {
+            running: false,
+            quit: false,
+            breakpoints: HashSet::new(),
+            run_to: None,
+            cursor: emulator.pc(),
+            memory_view: None,
+            prompt: None,
+            message: String::from("Paused"),
+        };
+
+        Ok(TuiDebugger { terminal, state })
+    }
+
+    pub fn quit(&self) -> bool {
+        self.state.quit
+    }
+
+    // Handles the pending terminal input, runs (the rest of) one emulated frame
+    // unless paused, and redraws the panes.
+    pub fn run_frame(&mut self, emulator: &mut Chip8, key: u16, tracer: &mut dyn Tracer, cheats: &mut Cheats) -> io::Result<()> {
+        let state = &mut self.state;
+
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key_event) = event::read()? {
+                if key_event.kind == KeyEventKind::Press {
+                    state.handle_key(key_event.code, emulator, key, tracer, cheats);
+                }
+            }
+        }
+
+        if state.running {
+            if !emulator.frame_pending() {
+                emulator.begin_frame(key);
+                tracer.frame_started(emulator);
+            }
+
+            while emulator.frame_pending() {
+                if !state.step(emulator, key, tracer) {
+                    break;
+                }
+
+                let pc = emulator.pc();
+                if state.breakpoints.contains(&pc) || state.run_to == Some(pc) {
+                    state.pause(emulator, format!("Breakpoint at #{:03X}", pc));
+                    break;
+                }
+            }
+        }
+
+        self.terminal.draw(|frame| state.draw(frame, emulator))?;
+
+        Ok(())
+    }
+}
+
+impl Drop for TuiDebugger {
+    fn drop(&mut self) {
+        restore_terminal();
+        let _ = self.terminal.show_cursor();
+        // back to the default hook, the panic hook cannot change while panicking
+        if !std::thread::panicking() {
+            let _ = std::panic::take_hook();
+        }
+    }
+}
+
+impl DebuggerState {
+    // Executes one instruction, starting a new frame when the current one is
+    // over. A failing instruction pauses the machine instead of quitting.
+    fn step(&mut self, emulator: &mut Chip8, key: u16, tracer: &mut dyn Tracer) -> bool {
+        if !emulator.frame_pending() {
+            emulator.begin_frame(key);
+            tracer.frame_started(emulator);
+        }
+
+        match emulator.step_traced(tracer) {
+            Ok(_) => true,
+            Err(e) => {
+                self.pause(emulator, e.to_string());
+                false
+            }
+        }
+    }
+
+    fn pause(&mut self, emulator: &Chip8, message: String) {
+        self.running = false;
+        self.run_to = None;
+        self.cursor = emulator.pc();
+        self.message = message;
+    }
+
+    fn handle_key(&mut self, code: KeyCode, emulator: &mut Chip8, key: u16, tracer: &mut dyn Tracer, cheats: &mut Cheats) {
+        if let Some(prompt) = self.prompt.as_mut() {
+            match code {
+                KeyCode::Char(c) => prompt.push(c),
+                KeyCode::Backspace => {
+                    prompt.pop();
+                }
+                KeyCode::Esc => self.prompt = None,
+                KeyCode::Enter => {
+                    let command = self.prompt.take().unwrap_or_default();
+                    self.message = match self.execute_command(&command, emulator, cheats) {
+                        Ok(None) => format!(":{}", command),
+                        Ok(Some(result)) => format!(":{} - {}", command, result),
+                        Err(e) => format!(":{} - {}", command, e),
+                    };
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char(' ') => {
+                if self.running {
+                    self.pause(emulator, String::from("Paused"));
+                } else {
+                    self.running = true;
+                    self.message = String::from("Running");
+                }
+            }
+            KeyCode::Char('s') if !self.running => {
+                let stepped = self.step(emulator, key, tracer);
+                if stepped {
+                    self.pause(emulator, String::from("Step"));
+                }
+            }
+            KeyCode::Char('f') if !self.running => {
+                let mut stepped = self.step(emulator, key, tracer);
+                while stepped && emulator.frame_pending() {
+                    stepped = self.step(emulator, key, tracer);
+                }
+                if stepped {
+                    self.pause(emulator, String::from("Frame"));
+                }
+            }
+            KeyCode::Char('g') if !self.running => {
+                self.run_to = Some(self.cursor);
+                self.running = true;
+                self.message = format!("Running to #{:03X}", self.cursor);
+            }
+            KeyCode::Char('b') => {
+                if self.breakpoints.contains(&self.cursor) {
+                    self.breakpoints.remove(&self.cursor);
+                } else {
+                    self.breakpoints.insert(self.cursor);
+                }
+            }
+            KeyCode::Up => self.cursor = self.cursor.saturating_sub(2),
+            KeyCode::Down => self.cursor = self.cursor.saturating_add(2).min(MEMORY_SIZE as u16 - 2),
+            KeyCode::PageUp => {
+                let start = self.memory_start(emulator);
+                self.memory_view = Some(start.saturating_sub(0x40));
+            }
+            KeyCode::PageDown => {
+                let start = self.memory_start(emulator);
+                self.memory_view = Some(start.saturating_add(0x40).min(MEMORY_SIZE as u16 - 1));
+            }
+            KeyCode::Char(':') => self.prompt = Some(String::new()),
+            _ => {}
+        }
+    }
+
+    // Prompt commands:
+    //   v3=1f, i=300, pc=200, dt=10, st=0   edit a register (values in hex)
+    //   poke 300 a2 1e ...                  write bytes starting at an address
+    //   mem 300 / mem                       show memory from an address / follow I
+    //   goto 300                            move the disassembly cursor
+    //   search / search changed|same|up|down|=3
+    //                                       start a cheat search / narrow it down
+    //   freeze 2f0 3 / freeze v3 3          hold a value, written before every frame
+    //   unfreeze 2f0 / unfreeze             release a value / all of them
+    //   cheat lives 2f0 3 [once]            save a cheat for the program and turn it on
+    //   cheat lives                         turn a saved cheat on
+    // Some commands answer with a result shown after them.
+    fn execute_command(&mut self, command: &str, emulator: &mut Chip8, cheats: &mut Cheats) -> Result<Option<String>, String> {
+        let mut words = command.split_whitespace();
+        let Some(first) = words.next() else {
+            return Ok(None);
+        };
+
+        if let Some((register, value)) = first.split_once('=') {
+            let value = parse_hex(value)?;
+            match register.to_ascii_lowercase().as_str() {
+                "i" => emulator.set_i(value),
+                "pc" => {
+                    let value = in_memory(value)?;
+                    emulator.set_pc(value);
+                    self.cursor = value;
+                }
+                "dt" => emulator.set_delay_timer(value as u8),
+                "st" => emulator.set_sound_timer(value as u8),
+                name => {
+                    let idx = name
+                        .strip_prefix('v')
+                        .and_then(|idx| usize::from_str_radix(idx, 16).ok())
+                        .filter(|idx| *idx < REGISTERS_COUNT)
+                        .ok_or("unknown register")?;
+                    emulator.registers_mut()[idx] = value as u8;
+                }
+            }
+            return Ok(None);
+        }
+
+        match first {
+            "poke" => {
+                let addr = parse_hex(words.next().ok_or("missing address")?)? as usize;
+                let bytes = words
+                    .map(|b| parse_hex(b).map(|b| b as u8))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                if addr + bytes.len() > MEMORY_SIZE {
+                    return Err(String::from("out of memory bounds"));
+                }
+                emulator.memory_mut()[addr..addr + bytes.len()].copy_from_slice(&bytes);
+                Ok(None)
+            }
+            "mem" => {
+                self.memory_view = words.next().map(parse_address).transpose()?;
+                Ok(None)
+            }
+            "goto" => {
+                self.cursor = parse_address(words.next().ok_or("missing address")?)?;
+                Ok(None)
+            }
+            "search" => {
+                let comparison = words.next().map(str::parse::<Comparison>).transpose().map_err(|e| e.to_string())?;
+                cheats.search(emulator, comparison).map(Some).map_err(|e| e.to_string())
+            }
+            "freeze" => {
+                let target = parse_target(words.next().ok_or("missing address or register")?)?;
+                let value = parse_byte(words.next().ok_or("missing value")?)?;
+                cheats.activate(&Cheat { name: String::new(), target, value, freeze: true }, emulator);
+                Ok(None)
+            }
+            "unfreeze" => {
+                cheats.unfreeze(words.next().map(parse_target).transpose()?);
+                Ok(None)
+            }
+            "cheat" => {
+                let name = words.next().ok_or("missing name")?;
+                match words.next() {
+                    Some(target) => {
+                        let target = parse_target(target)?;
+                        let value = parse_byte(words.next().ok_or("missing value")?)?;
+                        let cheat = Cheat { name: name.to_string(), target, value, freeze: words.next() != Some("once") };
+                        cheats.activate(&cheat, emulator);
+                        cheats.save(cheat).map_err(|e| e.to_string())?;
+                        Ok(Some(String::from("saved")))
+                    }
+                    None => {
+                        cheats.enable(name, emulator).map_err(|e| e.to_string())?;
+                        Ok(None)
+                    }
+                }
+            }
+            _ => Err(String::from("unknown command")),
+        }
+    }
+
+    fn memory_start(&self, emulator: &Chip8) -> u16 {
+        self.memory_view.unwrap_or(emulator.i()) & !(MEMORY_ROW as u16 - 1)
+    }
+
+    ////// Drawing
+    fn draw(&self, frame: &mut Frame, emulator: &Chip8) {
+        let [main, status] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(1)])
+            .areas(frame.area());
+        let [disassembly, right] = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Length(34), Constraint::Min(0)])
+            .areas(main);
+        let [state, memory] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(8), Constraint::Min(0)])
+            .areas(right);
+        let [registers, stack, keypad] = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(0), Constraint::Length(10), Constraint::Length(14)])
+            .areas(state);
+
+        self.draw_disassembly(frame, disassembly, emulator);
+        draw_registers(frame, registers, emulator);
+        draw_stack(frame, stack, emulator);
+        draw_keypad(frame, keypad, emulator);
+        self.draw_memory(frame, memory, emulator);
+
+        let status_line = match &self.prompt {
+            Some(prompt) => format!(":{}", prompt),
+            None => format!("{}  |  {}", self.message, HELP),
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+
+    fn draw_disassembly(&self, frame: &mut Frame, area: Rect, emulator: &Chip8) {
+        let rows = area.height.saturating_sub(2);
+        let start = self.cursor.saturating_sub(rows / 2 * 2);
+
+        let lines: Vec<Line> = (0..rows)
+            .map(|row| start + row * 2)
+            .take_while(|addr| (*addr as usize) < MEMORY_SIZE - 1)
+            .map(|addr| {
+                let marker = if addr == emulator.pc() { '>' } else { ' ' };
+                let breakpoint = if self.breakpoints.contains(&addr) { '*' } else { ' ' };
+                let memory = emulator.memory();
+                let text = format!(
+                    "{}{}{:03X}  {:02X}{:02X}  {}",
+                    breakpoint,
+                    marker,
+                    addr,
+                    memory[addr as usize],
+                    memory[addr as usize + 1],
+                    disassemble_at(memory, addr as usize)
+                );
+
+                let mut style = Style::default();
+                if addr == emulator.pc() {
+                    style = style.fg(Color::Yellow).add_modifier(Modifier::BOLD);
+                }
+                if self.breakpoints.contains(&addr) {
+                    style = style.fg(Color::Red);
+                }
+                if addr == self.cursor {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                Line::styled(text, style)
+            })
+            .collect();
+
+        let title = if self.running { " Disassembly (running) " } else { " Disassembly " };
+        frame.render_widget(Paragraph::new(lines).block(bordered(title)), area);
+    }
+
+    fn draw_memory(&self, frame: &mut Frame, area: Rect, emulator: &Chip8) {
+        let rows = area.height.saturating_sub(2) as usize;
+        let start = self.memory_start(emulator) as usize;
+        let i = emulator.i() as usize;
+        let memory = emulator.memory();
+
+        let lines: Vec<Line> = (0..rows)
+            .map(|row| start + row * MEMORY_ROW)
+            .take_while(|addr| *addr < MEMORY_SIZE)
+            .map(|addr| {
+                let mut spans = vec![Span::styled(format!("{:03X} ", addr), Style::default().fg(Color::DarkGray))];
+                let row = &memory[addr..(addr + MEMORY_ROW).min(MEMORY_SIZE)];
+                for (offset, byte) in (addr..).zip(row) {
+                    let style = if offset == i {
+                        Style::default().fg(Color::Black).bg(Color::Yellow)
+                    } else {
+                        Style::default()
+                    };
+                    spans.push(Span::raw(" "));
+                    spans.push(Span::styled(format!("{:02X}", byte), style));
+                }
+                Line::from(spans)
+            })
+            .collect();
+
+        let title = match self.memory_view {
+            Some(_) => " Memory ",
+            None => " Memory (following I) ",
+        };
+        frame.render_widget(Paragraph::new(lines).block(bordered(title)), area);
+    }
+}
+
+fn draw_registers(frame: &mut Frame, area: Rect, emulator: &Chip8) {
+    let registers = emulator.registers();
+    let mut lines: Vec<Line> = (0..4)
+        .map(|row| {
+            let text: Vec<String> = (0..4)
+                .map(|col| row * 4 + col)
+                .map(|idx| format!("V{:X} {:02X}", idx, registers[idx]))
+                .collect();
+            Line::raw(text.join("  "))
+        })
+        .collect();
+
+    lines.push(Line::raw(format!("I  {:03X}  PC {:03X}", emulator.i(), emulator.pc())));
+    lines.push(Line::raw(format!("DT {:02X}   ST {:02X}", emulator.delay_timer(), emulator.sound_timer())));
+
+    frame.render_widget(Paragraph::new(lines).block(bordered(" Registers ")), area);
+}
+
+fn draw_stack(frame: &mut Frame, area: Rect, emulator: &Chip8) {
+    // top of the stack first
+    let lines: Vec<Line> = emulator
+        .stack()
+        .as_slice()
+        .iter()
+        .rev()
+        .map(|addr| Line::raw(format!("{:03X}", addr)))
+        .collect();
+
+    frame.render_widget(Paragraph::new(lines).block(bordered(" Stack ")), area);
+}
+
+fn draw_keypad(frame: &mut Frame, area: Rect, emulator: &Chip8) {
+    let lines: Vec<Line> = KEYPAD_LAYOUT
+        .iter()
+        .map(|row| {
+            let spans: Vec<Span> = row
+                .iter()
+                .map(|key| {
+                    let style = if emulator.keypad() & 1 << key != 0 {
+                        Style::default().fg(Color::Black).bg(Color::Green)
+                    } else {
+                        Style::default()
+                    };
+                    Span::styled(format!(" {:X} ", key), style)
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect();
+
+    frame.render_widget(Paragraph::new(lines).block(bordered(" Keypad ")), area);
+}
+
+fn bordered(title: &str) -> Block<'_> {
+    Block::default().borders(Borders::ALL).title(title)
+}
+
+fn parse_hex(value: &str) -> Result<u16, String> {
+    let digits = value.trim_start_matches("0x").trim_start_matches('#');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex value '{}'", value))
+}
+
+fn parse_byte(value: &str) -> Result<u8, String> {
+    u8::try_from(parse_hex(value)?).map_err(|_| format!("'{}' is more than a byte", value))
+}
+
+// An address in memory, in hex
+fn parse_address(value: &str) -> Result<u16, String> {
+    in_memory(parse_hex(value)?)
+}
+
+fn in_memory(addr: u16) -> Result<u16, String> {
+    if (addr as usize) < MEMORY_SIZE {
+        Ok(addr)
+    } else {
+        Err(format!("#{:X} is out of memory", addr))
+    }
+}
+
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen);
+}
+
+fn parse_target(value: &str) -> Result<Target, String> {
+    value.parse().map_err(|e: CheatError| e.to_string())
+}
@@ -0,0 +1,69 @@
+// Notices when a program file changes on disk, for `--watch`
+//
+// The file is polled for its size and modification time a few times a
+// second. A change is reported once the file stopped changing for a poll, so
+// a ROM is not reloaded while the assembler is still writing it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// What is compared between two polls, None while the file is missing
+type Stamp = Option<(SystemTime, u64)>;
+
+pub struct FileWatcher {
+    path: PathBuf,
+    // the stamp of the file as last loaded
+    loaded: Stamp,
+    // a new stamp seen at the last poll, waiting to settle
+    pending: Option<Stamp>,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Self {
+        FileWatcher {
+            path: path.to_path_buf(),
+            loaded: stamp(path),
+            pending: None,
+            last_poll: Instant::now(),
+        }
+    }
+
+    // Watches `path` instead, as it is now
+    pub fn watch(&mut self, path: &Path) {
+        *self = FileWatcher::new(path);
+    }
+
+    // Whether the file changed and settled since it was last loaded. The
+    // change is only reported once.
+    pub fn changed(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let current = stamp(&self.path);
+        if current == self.loaded {
+            self.pending = None;
+            return false;
+        }
+
+        // a file that is gone, or still being written, is not loaded yet
+        if current.is_none() || self.pending != Some(current) {
+            self.pending = Some(current);
+            return false;
+        }
+
+        self.loaded = current;
+        self.pending = None;
+        true
+    }
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}